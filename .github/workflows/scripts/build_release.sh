@@ -5,7 +5,7 @@ ARCH=${1}
 echo "Installing cross..."
 cargo install cross --git https://github.com/cross-rs/cross
 echo "Building for arch ${ARCH}..."
-cross build --release --target ${ARCH}-unknown-linux-gnu --bin daemon --bin routing --bin runner --bin cron --bin logs --bin downloader --all-features
+cross build --release --target ${ARCH}-unknown-linux-gnu --bin daemon --bin routing --bin runner --bin cron --bin logs --bin downloader --bin registry --bin deadletters --bin queue --bin recorder --all-features
 echo "Copying bin files..."
 OUT_FOLDER="alfred"
 BIN_FOLDER="target/${ARCH}-unknown-linux-gnu/release"
@@ -13,6 +13,10 @@ mkdir $OUT_FOLDER
 cp $BIN_FOLDER/daemon $OUT_FOLDER/
 cp $BIN_FOLDER/cron $OUT_FOLDER/
 cp $BIN_FOLDER/downloader $OUT_FOLDER/
+cp $BIN_FOLDER/registry $OUT_FOLDER/
+cp $BIN_FOLDER/deadletters $OUT_FOLDER/
+cp $BIN_FOLDER/queue $OUT_FOLDER/
+cp $BIN_FOLDER/recorder $OUT_FOLDER/
 cp $BIN_FOLDER/logs $OUT_FOLDER/
 cp $BIN_FOLDER/routing $OUT_FOLDER/
 cp $BIN_FOLDER/runner $OUT_FOLDER/
@@ -1,21 +1,21 @@
-use log::info;
-use zmq2::Context;
+use log::{error, info};
 use alfred_core::AlfredModule;
+use alfred_core::broker::Broker;
+use alfred_core::config::Config;
+use alfred_core::error::Error;
 
-fn main() {
+#[tokio::main]
+async fn main() {
     env_logger::init();
     AlfredModule::manage_args("daemon", env!("CARGO_PKG_VERSION"));
     info!("Loading daemon...");
-    let config = alfred_core::config::Config::read(None);
-    let pub_port = config.alfred.pub_port;
-    let sub_port = config.alfred.sub_port;
-    let context = Context::new();
-    let xpub_sub = context.socket(zmq2::XPUB).expect("Failed to create XPUB socket");
-    let pub_xsub = context.socket(zmq2::XSUB).expect("Failed to create XSUB socket");
-    info!("Binding port {} for publish...", pub_port);
-    pub_xsub.bind(format!("tcp://*:{pub_port}").as_str()).expect("Failed to bind XPUB socket");
-    info!("Binding port {} for subscription...", sub_port);
-    xpub_sub.bind(format!("tcp://*:{sub_port}").as_str()).expect("Failed to bind XSUB socket");
+    let config = Config::read(None);
+    if let Err(error) = run(&config).await {
+        error!("{error}");
+        std::process::exit(1);
+    }
+}
 
-    zmq2::proxy(&pub_xsub, &xpub_sub).expect("Failed starting zmq proxy");
+async fn run(config: &Config) -> Result<(), Error> {
+    Broker::bind(config).await?.run().await
 }
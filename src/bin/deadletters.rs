@@ -0,0 +1,101 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::error::Error;
+use std::sync::Arc;
+use log::{info, warn};
+use tokio::sync::Mutex;
+use alfred_core::AlfredModule;
+use alfred_core::connection::Connection;
+use alfred_core::dead_letter::{DeadLetter, DEAD_LETTER_TOPIC};
+use alfred_core::message::Message;
+
+const MODULE_NAME: &str = "deadletters";
+/// Oldest dead letters are dropped beyond this number.
+const MAX_DEAD_LETTERS: usize = 1000;
+/// Requests answered with the kept dead letters in the params of the reply (id -> description).
+const LIST_TOPIC_REQUEST: &str = "dlq.list";
+/// Requests publishing the kept dead letters again on their original topic, then forgetting them.
+/// The `id` or `topic` params restrict the dead letters published; the reply text is their number.
+const REPUBLISH_TOPIC_REQUEST: &str = "dlq.republish";
+const ID_PARAM: &str = "id";
+const TOPIC_PARAM: &str = "topic";
+
+type DeadLetters = Arc<Mutex<VecDeque<(String, DeadLetter)>>>;
+
+fn description(dead_letter: &DeadLetter) -> String {
+    format!("{} from {} on topic {}", dead_letter.reason, dead_letter.module, dead_letter.topic)
+}
+
+async fn keep(dead_letters: &DeadLetters, message: &Message) {
+    match DeadLetter::from_message(message) {
+        Ok(dead_letter) => {
+            warn!("Dead letter {}: {}", message.id, description(&dead_letter));
+            let mut dead_letters = dead_letters.lock().await;
+            if dead_letters.len() == MAX_DEAD_LETTERS {
+                dead_letters.pop_front();
+            }
+            dead_letters.push_back((message.id.clone(), dead_letter));
+        },
+        // e.g. malformed messages, whose raw bytes cannot be published again
+        Err(e) => warn!("Dead letter {} cannot be republished ({e}): {}", message.id, message.text)
+    }
+}
+
+async fn republish(connection: &Connection, dead_letters: &DeadLetters, request: &Message) -> usize {
+    let selected = |(id, dead_letter): &(String, DeadLetter)| {
+        request.params.get(ID_PARAM).is_none_or(|selected_id| selected_id == id)
+            && request.params.get(TOPIC_PARAM).is_none_or(|topic| *topic == dead_letter.topic)
+    };
+    let mut dead_letters = dead_letters.lock().await;
+    let (republished, kept): (VecDeque<_>, VecDeque<_>) = dead_letters.drain(..).partition(selected);
+    *dead_letters = kept;
+    drop(dead_letters);
+    let mut count = 0;
+    for (id, dead_letter) in republished {
+        match connection.send(&dead_letter.topic, &dead_letter.message).await {
+            Ok(()) => {
+                info!("Dead letter {id} published again on topic {}", dead_letter.topic);
+                count += 1;
+            },
+            Err(e) => warn!("Unable to publish dead letter {id} again: {e}")
+        }
+    }
+    count
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    env_logger::init();
+    info!("Loading deadletters module...");
+    let mut module = AlfredModule::new(MODULE_NAME, env!("CARGO_PKG_VERSION")).await?;
+    let dead_letters = DeadLetters::default();
+
+    let kept = dead_letters.clone();
+    module.on(DEAD_LETTER_TOPIC, move |_, message| {
+        let dead_letters = kept.clone();
+        async move {
+            keep(&dead_letters, &message).await;
+            Ok(None)
+        }
+    }).await?;
+    let listed = dead_letters.clone();
+    module.on(LIST_TOPIC_REQUEST, move |_, _| {
+        let dead_letters = listed.clone();
+        async move {
+            let params: BTreeMap<String, String> = dead_letters.lock().await.iter()
+                .map(|(id, dead_letter)| (id.clone(), description(dead_letter)))
+                .collect();
+            Ok(Some(Message { params, ..Message::default() }))
+        }
+    }).await?;
+    let connection = module.connection.clone();
+    module.on(REPUBLISH_TOPIC_REQUEST, move |_, request| {
+        let connection = connection.clone();
+        let dead_letters = dead_letters.clone();
+        async move {
+            let count = republish(&connection, &dead_letters, &request).await;
+            Ok(Some(Message { text: count.to_string(), ..Message::default() }))
+        }
+    }).await?;
+    module.run().await?;
+    Ok(())
+}
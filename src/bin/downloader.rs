@@ -86,7 +86,7 @@ async fn get_latest_version(repo: &str) -> Result<String, Box<dyn Error>> {
     if response.status().is_redirection() {
         if let Some(location) = response.headers().get("Location") {
             let location_str = location.to_str().unwrap_or_default();
-            return Ok(location_str.split('/').last()
+            return Ok(location_str.split('/').next_back()
                 .unwrap_or("latest").to_string()
             );
         }
@@ -118,6 +118,6 @@ async fn main() -> Result<(), Box<dyn Error>>{
                 }
             }
         }
-    };
+    }
     Ok(())
 }
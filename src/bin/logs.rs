@@ -18,13 +18,16 @@ async fn main() -> Result<(), Error> {
             MessageType::Text => {
                 info!("{}: {}", topic, message.text);
             },
-            MessageType::Unknown | MessageType::Audio | MessageType::Photo => {
-                info!("{}[{}]: {}", topic, message.message_type, message.text);
+            MessageType::Unknown | MessageType::Audio | MessageType::Photo | MessageType::Video
+            | MessageType::Document | MessageType::Location | MessageType::Command | MessageType::Event
+            | MessageType::Json | MessageType::Error | MessageType::Custom(_) | MessageType::Named(_) => {
+                info!("{}[{}]: {} ({} bytes)", topic, message.message_type, message.text, message.data.len());
             },
             MessageType::ModuleInfo => {
                 info!("Module Info: {}\n\t{:?}", message.text, message.params);
             }
         }
         debug!("response_topics: {:?}", message.response_topics);
+        debug!("id: {}, correlation_id: {}, timestamp: {}, hops: {}", message.id, message.correlation_id, message.timestamp, message.hops);
     }
 }
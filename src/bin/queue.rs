@@ -0,0 +1,65 @@
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use log::{info, warn};
+use tokio::sync::Mutex;
+use alfred_core::AlfredModule;
+use alfred_core::connection::{MODULE_OFFLINE_TOPIC, MODULE_ONLINE_TOPIC};
+use alfred_core::durable::{DurableSubscription, DURABLE_SUBSCRIBE_TOPIC, DURABLE_UNSUBSCRIBE_TOPIC};
+use alfred_core::queue::{set_online, Queues};
+
+const MODULE_NAME: &str = "queue";
+const QUEUE_DIR: &str = "queue";
+/// Time given to the running modules to answer the info request sent at startup.
+const DISCOVERY_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    env_logger::init();
+    info!("Loading queue module...");
+    let mut module = AlfredModule::new(MODULE_NAME, env!("CARGO_PKG_VERSION")).await?;
+    let queues = Queues::load(PathBuf::from(&module.config.alfred.tmp_dir).join(QUEUE_DIR));
+    for topic in [DURABLE_SUBSCRIBE_TOPIC, DURABLE_UNSUBSCRIBE_TOPIC, MODULE_ONLINE_TOPIC, MODULE_OFFLINE_TOPIC] {
+        module.listen(topic).await?;
+    }
+    // the reliable messages stored are acknowledged by their module, not by the queue
+    for topic in queues.topics() {
+        module.connection.listen_passive(&topic).await?;
+    }
+    let queues = Arc::new(Mutex::new(queues));
+    let online: BTreeSet<String> = module.discover_modules(DISCOVERY_TIMEOUT).await?.into_iter().map(|info| info.name).collect();
+    let modules = queues.lock().await.modules();
+    for queue_module in modules {
+        set_online(&module.connection, &queues, &queue_module, online.contains(&queue_module)).await;
+    }
+
+    loop {
+        let (topic, message) = module.receive().await?;
+        match topic.as_str() {
+            DURABLE_SUBSCRIBE_TOPIC | DURABLE_UNSUBSCRIBE_TOPIC => {
+                let subscription = match DurableSubscription::from_message(&message) {
+                    Ok(subscription) => subscription,
+                    Err(e) => { warn!("Invalid durable subscription: {e}"); continue; }
+                };
+                let topic_changed = if topic == DURABLE_SUBSCRIBE_TOPIC {
+                    info!("Durable subscription of {} to {}", subscription.module, subscription.topic);
+                    queues.lock().await.subscribe(subscription.clone())
+                } else {
+                    info!("Durable subscription of {} to {} removed", subscription.module, subscription.topic);
+                    queues.lock().await.unsubscribe(&subscription)
+                };
+                if topic_changed && topic == DURABLE_SUBSCRIBE_TOPIC {
+                    module.connection.listen_passive(&subscription.topic).await?;
+                } else if topic_changed {
+                    module.connection.unlisten(&subscription.topic).await?;
+                }
+            },
+            MODULE_ONLINE_TOPIC | MODULE_OFFLINE_TOPIC => {
+                set_online(&module.connection, &queues, &message.text, topic == MODULE_ONLINE_TOPIC).await;
+            },
+            _ => queues.lock().await.store(&topic, &message)
+        }
+    }
+}
@@ -0,0 +1,63 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::time::Duration;
+use log::info;
+use alfred_core::clap::{value_parser, Arg, ArgAction, ArgMatches, Command};
+use alfred_core::config::Config;
+use alfred_core::recorder;
+use alfred_core::transport::{Transport, ZmqTransport};
+
+const MODULE_NAME: &str = "recorder";
+const RECORD_COMMAND: &str = "record";
+const REPLAY_COMMAND: &str = "replay";
+const FILE_ARG: &str = "file";
+const SPEED_ARG: &str = "speed";
+const TOPIC_ARG: &str = "topic";
+
+fn command() -> Command {
+    let file = Arg::new(FILE_ARG).required(true).help("Recording file");
+    Command::new(MODULE_NAME)
+        .version(env!("CARGO_PKG_VERSION"))
+        .about("Records the messages published on the bus and publishes them again")
+        .subcommand_required(true)
+        .subcommand(Command::new(RECORD_COMMAND).about("Records every message until stopped").arg(file.clone()))
+        .subcommand(Command::new(REPLAY_COMMAND).about("Publishes a recording again")
+            .arg(file)
+            .arg(Arg::new(SPEED_ARG).long(SPEED_ARG).value_parser(value_parser!(f64)).default_value("1")
+                .help("Speed factor of the original timing, 0 to publish without waiting"))
+            .arg(Arg::new(TOPIC_ARG).long(TOPIC_ARG).action(ArgAction::Append)
+                .help("Only publishes the topics starting with this prefix (repeatable)")))
+}
+
+fn arg<'a>(matches: &'a ArgMatches, id: &str) -> Result<&'a String, String> {
+    matches.get_one::<String>(id).ok_or_else(|| format!("Missing argument {id}"))
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    env_logger::init();
+    let matches = command().get_matches();
+    let config = Config::read(Some(MODULE_NAME));
+    // raw frames are recorded, as published: params, copies and malformed messages included
+    let (mut subscriber, mut publisher) = ZmqTransport::new(&config).connect_raw().await?;
+    match matches.subcommand() {
+        Some((RECORD_COMMAND, matches)) => {
+            let path = arg(matches, FILE_ARG)?;
+            let mut writer = BufWriter::new(File::create(path)?);
+            info!("Recording to {path}...");
+            recorder::record(subscriber.as_mut(), &mut writer).await?;
+        },
+        Some((REPLAY_COMMAND, matches)) => {
+            let path = arg(matches, FILE_ARG)?;
+            let speed = matches.get_one::<f64>(SPEED_ARG).copied().unwrap_or(1.0);
+            let topics: Vec<String> = matches.get_many::<String>(TOPIC_ARG).unwrap_or_default().cloned().collect();
+            info!("Replaying {path}...");
+            let reader = &mut BufReader::new(File::open(path)?);
+            let handshake_timeout = Duration::from_millis(config.alfred.handshake_timeout);
+            recorder::replay(subscriber.as_mut(), publisher.as_mut(), reader, speed, &topics, handshake_timeout).await?;
+        },
+        _ => return Err("Unknown command".into())
+    }
+    Ok(())
+}
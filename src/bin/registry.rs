@@ -0,0 +1,72 @@
+use std::error::Error;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use log::{debug, info, warn};
+use tokio::sync::Mutex;
+use alfred_core::AlfredModule;
+use alfred_core::connection::{MODULE_HEARTBEAT_TOPIC, MODULE_INFO_TOPIC_REQUEST, MODULE_INFO_TOPIC_RESPONSE};
+use alfred_core::message::Message;
+use alfred_core::registry::{Directory, CAPABILITY_PARAM, LIST_TOPIC_REQUEST, LOOKUP_TOPIC_REQUEST};
+
+const MODULE_NAME: &str = "registry";
+const SNAPSHOT_FILENAME: &str = "registry.toml";
+const SNAPSHOT_INTERVAL: Duration = Duration::from_secs(10);
+
+async fn save_snapshots(directory: Arc<Mutex<Directory>>, path: PathBuf) {
+    let mut interval = tokio::time::interval(SNAPSHOT_INTERVAL);
+    loop {
+        interval.tick().await;
+        let mut directory = directory.lock().await;
+        if !directory.is_changed() { continue; }
+        match directory.save(&path) {
+            Ok(()) => debug!("Snapshot saved to {}", path.display()),
+            Err(e) => warn!("Unable to save the snapshot to {}: {e}", path.display())
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    env_logger::init();
+    info!("Loading registry module...");
+    let mut module = AlfredModule::new(MODULE_NAME, env!("CARGO_PKG_VERSION")).await?;
+    let snapshot = PathBuf::from(&module.config.alfred.tmp_dir).join(SNAPSHOT_FILENAME);
+    let directory = Arc::new(Mutex::new(Directory::load(&snapshot)));
+
+    let info_directory = directory.clone();
+    module.on(MODULE_INFO_TOPIC_RESPONSE, move |_, message| {
+        let directory = info_directory.clone();
+        async move {
+            directory.lock().await.update_info(&message);
+            Ok(None)
+        }
+    }).await?;
+    let heartbeat_directory = directory.clone();
+    module.on(MODULE_HEARTBEAT_TOPIC, move |_, message| {
+        let directory = heartbeat_directory.clone();
+        async move {
+            directory.lock().await.update_heartbeat(&message);
+            Ok(None)
+        }
+    }).await?;
+    let list_directory = directory.clone();
+    module.on(LIST_TOPIC_REQUEST, move |_, message| {
+        let directory = list_directory.clone();
+        async move {
+            let params = directory.lock().await.list(message.params.get(CAPABILITY_PARAM).map(String::as_str));
+            Ok(Some(Message { params, ..Message::default() }))
+        }
+    }).await?;
+    let lookup_directory = directory.clone();
+    module.on(LOOKUP_TOPIC_REQUEST, move |_, message| {
+        let directory = lookup_directory.clone();
+        async move { Ok(Some(directory.lock().await.lookup(&message.text))) }
+    }).await?;
+
+    tokio::spawn(save_snapshots(directory, snapshot));
+    // modules started before the registry announce themselves again
+    module.send(MODULE_INFO_TOPIC_REQUEST, &Message::default()).await?;
+    module.run().await?;
+    Ok(())
+}
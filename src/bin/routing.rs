@@ -1,6 +1,7 @@
 use std::collections::HashMap;
 use std::error::Error;
 use std::fs;
+use std::sync::Arc;
 use serde_derive::Deserialize;
 use alfred_core::log::{debug, info, warn};
 use alfred_core::AlfredModule;
@@ -48,23 +49,28 @@ async fn main() -> Result<(), Box<dyn Error>> {
     let mut routing_hashmap = HashMap::new();
     for routing in routing_config.routing {
         debug!("{} -> {}", routing.from_topic, routing.to_topic.clone());
-        module.listen(routing.from_topic.as_str()).await?;
-        if !routing_hashmap.contains_key(&routing.from_topic) {
-            routing_hashmap.insert(routing.from_topic.clone(), vec![]);
-        }
-        routing_hashmap
-            .get_mut(&routing.from_topic)
-            .unwrap_or_else(|| panic!("Unable to insert new routing with key {}", routing.from_topic))
-            .push(routing);
+        routing_hashmap.entry(routing.from_topic.clone()).or_insert_with(Vec::new).push(routing);
     }
-    loop {
-        let (topic, message) = module.receive().await?;
-        let routing_items = routing_hashmap.get(&topic);
-
-        for routing_item in routing_items.unwrap_or(&Vec::new()) {
-            let routing_message = routing_item.message.clone()
-                .map_or_else(|| message.clone(), |routing_message| routing_message.generate_message(&message));
-            module.send(routing_item.to_topic.as_str(), &routing_message).await?;
-        }
+    for (from_topic, routing_items) in routing_hashmap {
+        let connection = module.connection.clone();
+        let routing_items = Arc::new(routing_items);
+        module.on(from_topic.as_str(), move |_, message| {
+            let connection = connection.clone();
+            let routing_items = routing_items.clone();
+            async move {
+                let message = message.forward();
+                for routing_item in routing_items.iter() {
+                    let routing_message = routing_item.message.clone()
+                        .map_or_else(|| message.clone(), |routing_message| routing_message.generate_message(&message));
+                    connection.send(routing_item.to_topic.as_str(), &routing_message).await?;
+                }
+                Ok(None)
+            }
+        }).await?;
     }
+    // topics received for the prefix of a route, e.g. `chat.response` for `chat`, have no route either
+    module.dead_letter_prefixed();
+    // routes forward the messages in the order they were received
+    module.run_sequential().await?;
+    Ok(())
 }
@@ -34,7 +34,7 @@ fn main() {
 
     for module in modules {
         if Path::new(&format!("./{module}")).exists() {
-            info!("running module '{}'...", module);
+            info!("running module '{module}'...");
             Command::new("sh")
                 .arg("-c")
                 .env("RUST_LOG", rust_log_env.clone())
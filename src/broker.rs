@@ -0,0 +1,514 @@
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use bytes::Bytes;
+use log::{debug, error, info, warn};
+use tokio::io::{AsyncRead, AsyncWrite, BufReader, BufWriter};
+use tokio::sync::{mpsc, oneshot, Mutex};
+use tokio::task::{JoinHandle, JoinSet};
+use tokio::time::MissedTickBehavior;
+use crate::codec::{self, CodecKind, MessageCodec};
+use crate::config::{Config, UnroutedPolicy};
+use crate::connection::{MODULE_HEARTBEAT_TOPIC, MODULE_INFO_TOPIC_RESPONSE, MODULE_OFFLINE_TOPIC, MODULE_ONLINE_TOPIC, is_probe_topic};
+use crate::endpoint::{Endpoint, Listener, Stream};
+use crate::error::Error;
+use crate::message::{Message, MessageType};
+use crate::zmtp::{self, Frames, SUBSCRIBE, UNSUBSCRIBE};
+
+/// Messages waiting to be written to a slow subscriber before new ones are dropped.
+const SUBSCRIBER_QUEUE_SIZE: usize = 1000;
+/// Pause after a failed accept, e.g. when the process runs out of file descriptors.
+const ACCEPT_ERROR_DELAY: Duration = Duration::from_millis(100);
+/// Requests answered by the broker with the subscribed topic prefixes, in the params of the reply
+/// (prefix -> number of subscribers).
+pub const SUBSCRIPTIONS_TOPIC_REQUEST: &str = "broker.subscriptions";
+/// Topic of the warnings published for messages without subscribers, when enabled.
+pub const UNROUTED_TOPIC: &str = "broker.unrouted";
+const BROKER_NAME: &str = "broker";
+const REASON_DISCONNECTED: &str = "disconnected";
+const REASON_TIMEOUT: &str = "timeout";
+
+struct Subscriber {
+    subscriptions: Vec<Bytes>,
+    sender: mpsc::Sender<Frames>
+}
+
+impl Subscriber {
+    fn matches(&self, topic: &[u8]) -> bool {
+        self.subscriptions.iter().any(|subscription| topic.starts_with(subscription))
+    }
+
+    /// Whether the topic is subscribed by name, not only through the `""` wildcard of the logs or a recorder.
+    fn routes(&self, topic: &[u8]) -> bool {
+        self.subscriptions.iter().any(|subscription| !subscription.is_empty() && topic.starts_with(subscription))
+    }
+}
+
+/// Module announced by a heartbeat or its module info.
+struct Presence {
+    /// Publisher connection of the module: the module is offline once it is closed
+    publisher: u64,
+    last_seen: Instant
+}
+
+#[derive(Default)]
+struct Subscribers {
+    next_id: u64,
+    subscribers: HashMap<u64, Subscriber>
+}
+
+/// Forwards the messages received from the publishers to the subscribers of their topic.
+#[derive(Clone)]
+struct Router {
+    subscribers: Arc<Mutex<Subscribers>>,
+    modules: Arc<Mutex<HashMap<String, Presence>>>,
+    next_publisher: Arc<AtomicU64>,
+    codec: Arc<dyn MessageCodec>,
+    unrouted: UnroutedPolicy,
+    /// Time given to a new connection to complete the ZMTP handshake
+    handshake_timeout: Duration
+}
+
+impl Router {
+    fn new(codec: Box<dyn MessageCodec>, unrouted: UnroutedPolicy, handshake_timeout: Duration) -> Self {
+        Self {
+            subscribers: Arc::default(),
+            modules: Arc::default(),
+            next_publisher: Arc::default(),
+            codec: Arc::from(codec),
+            unrouted,
+            handshake_timeout
+        }
+    }
+
+    fn add_publisher(&self) -> u64 {
+        self.next_publisher.fetch_add(1, Ordering::Relaxed)
+    }
+
+    async fn add(&self, sender: mpsc::Sender<Frames>) -> u64 {
+        let mut subscribers = self.subscribers.lock().await;
+        let id = subscribers.next_id;
+        subscribers.next_id += 1;
+        subscribers.subscribers.insert(id, Subscriber { subscriptions: Vec::new(), sender });
+        id
+    }
+
+    async fn remove(&self, id: u64) {
+        self.subscribers.lock().await.subscribers.remove(&id);
+    }
+
+    async fn update_subscription(&self, id: u64, frames: &Frames) {
+        let Some((kind, topic)) = frames.first().and_then(|frame| frame.split_first()) else { return };
+        let topic = Bytes::copy_from_slice(topic);
+        let mut subscribers = self.subscribers.lock().await;
+        let Some(subscriber) = subscribers.subscribers.get_mut(&id) else { return };
+        match *kind {
+            SUBSCRIBE => subscriber.subscriptions.push(topic),
+            UNSUBSCRIBE => {
+                if let Some(index) = subscriber.subscriptions.iter().position(|subscription| *subscription == topic) {
+                    subscriber.subscriptions.swap_remove(index);
+                }
+            },
+            _ => debug!("Ignoring message sent by a subscriber")
+        }
+        drop(subscribers);
+    }
+
+    async fn route(&self, publisher: u64, frames: Frames) {
+        let Some(topic) = frames.first() else { return };
+        if topic == SUBSCRIPTIONS_TOPIC_REQUEST.as_bytes() {
+            self.answer_subscriptions(&frames).await;
+            return;
+        }
+        let heartbeat = topic == MODULE_HEARTBEAT_TOPIC.as_bytes();
+        if heartbeat || topic == MODULE_INFO_TOPIC_RESPONSE.as_bytes() {
+            self.module_seen(publisher, &frames).await;
+        }
+        // probes are expected to get lost until the subscription of the module is routed
+        if self.deliver(&frames).await || self.unrouted == UnroutedPolicy::Ignore || heartbeat
+            || topic == UNROUTED_TOPIC.as_bytes() || is_probe_topic(topic) {
+            return;
+        }
+        let topic = String::from_utf8_lossy(topic).to_string();
+        warn!("No subscribers for topic {topic}");
+        if self.unrouted == UnroutedPolicy::Publish {
+            let warning = Message {
+                message_type: MessageType::Error,
+                text: format!("No subscribers for topic {topic}"),
+                sender: BROKER_NAME.to_string(),
+                params: BTreeMap::from([(String::from("topic"), topic)]),
+                ..Message::default()
+            };
+            self.publish(UNROUTED_TOPIC, &warning).await;
+        }
+    }
+
+    /// Sends the message to the subscribers of its topic, returning whether one subscribed to it by name.
+    async fn deliver(&self, frames: &Frames) -> bool {
+        let Some(topic) = frames.first() else { return false };
+        let subscribers = self.subscribers.lock().await;
+        let mut routed = false;
+        for subscriber in subscribers.subscribers.values().filter(|subscriber| subscriber.matches(topic)) {
+            routed |= subscriber.routes(topic);
+            if subscriber.sender.try_send(frames.clone()).is_err() {
+                warn!("Subscriber queue full: dropping message on topic {}", String::from_utf8_lossy(topic));
+            }
+        }
+        drop(subscribers);
+        routed
+    }
+
+    async fn publish(&self, topic: &str, message: &Message) {
+        let mut message = message.clone();
+        message.stamp();
+        match self.codec.encode(&message) {
+            Ok(frame) => { self.deliver(&vec![Bytes::from(topic.to_string()), Bytes::from(frame)]).await; },
+            Err(e) => error!("Unable to encode the message for topic {topic}: {e}")
+        }
+    }
+
+    async fn module_seen(&self, publisher: u64, frames: &Frames) {
+        let module_name = match frames.get(1).map(|frame| codec::decode(frame)) {
+            Some(Ok(message)) if !message.text.is_empty() => message.text,
+            Some(Ok(_)) | None => return,
+            Some(Err(e)) => return warn!("Invalid module heartbeat: {e}")
+        };
+        let presence = Presence { publisher, last_seen: Instant::now() };
+        let online = self.modules.lock().await.insert(module_name.clone(), presence).is_none();
+        if online {
+            info!("Module {module_name} is online");
+            self.publish_presence(MODULE_ONLINE_TOPIC, &module_name, None).await;
+        }
+    }
+
+    /// Modules whose publisher connection is closed are offline.
+    async fn remove_publisher(&self, publisher: u64) {
+        let mut modules = self.modules.lock().await;
+        let offline: Vec<String> = modules.iter()
+            .filter(|(_, presence)| presence.publisher == publisher)
+            .map(|(module_name, _)| module_name.clone())
+            .collect();
+        modules.retain(|_, presence| presence.publisher != publisher);
+        drop(modules);
+        for module_name in offline {
+            self.module_offline(&module_name, REASON_DISCONNECTED).await;
+        }
+    }
+
+    /// Modules without heartbeat for longer than `timeout` are offline.
+    async fn expire_modules(&self, timeout: Duration) {
+        let mut modules = self.modules.lock().await;
+        let offline: Vec<String> = modules.iter()
+            .filter(|(_, presence)| presence.last_seen.elapsed() > timeout)
+            .map(|(module_name, _)| module_name.clone())
+            .collect();
+        modules.retain(|_, presence| presence.last_seen.elapsed() <= timeout);
+        drop(modules);
+        for module_name in offline {
+            self.module_offline(&module_name, REASON_TIMEOUT).await;
+        }
+    }
+
+    async fn module_offline(&self, module_name: &str, reason: &str) {
+        info!("Module {module_name} is offline ({reason})");
+        self.publish_presence(MODULE_OFFLINE_TOPIC, module_name, Some(reason)).await;
+    }
+
+    async fn publish_presence(&self, topic: &str, module_name: &str, reason: Option<&str>) {
+        let mut params = BTreeMap::from([(String::from("module"), module_name.to_string())]);
+        if let Some(reason) = reason {
+            params.insert(String::from("reason"), reason.to_string());
+        }
+        let event = Message {
+            message_type: MessageType::Event,
+            text: module_name.to_string(),
+            sender: BROKER_NAME.to_string(),
+            params,
+            ..Message::default()
+        };
+        self.publish(topic, &event).await;
+    }
+
+    /// Subscribed topic prefixes, with the number of subscribers of each one.
+    async fn subscriptions(&self) -> BTreeMap<String, usize> {
+        let mut subscriptions = BTreeMap::new();
+        for subscription in self.subscribers.lock().await.subscribers.values().flat_map(|subscriber| &subscriber.subscriptions) {
+            *subscriptions.entry(String::from_utf8_lossy(subscription).to_string()).or_default() += 1;
+        }
+        subscriptions
+    }
+
+    async fn answer_subscriptions(&self, frames: &Frames) {
+        let request = match frames.get(1).map(|frame| codec::decode(frame)) {
+            Some(Ok(request)) => request,
+            Some(Err(e)) => return warn!("Invalid subscriptions request: {e}"),
+            None => return warn!("Empty subscriptions request")
+        };
+        let params = self.subscriptions().await.into_iter()
+            .map(|(subscription, subscribers)| (subscription, subscribers.to_string()))
+            .collect();
+        let response = Message { message_type: MessageType::Text, sender: BROKER_NAME.to_string(), params, ..Message::default() };
+        match request.reply_with(response) {
+            Ok((topic, reply)) => self.publish(&topic, &reply).await,
+            Err(e) => warn!("Unable to answer the subscriptions request: {e}")
+        }
+    }
+}
+
+/// Pub/sub proxy between the modules, speaking ZMTP 3.0 as the libzmq `XSUB`/`XPUB` proxy did.
+///
+/// Modules publish to the endpoints of [`Config::get_alfred_pub_bind`]
+/// and subscribe to the endpoints of [`Config::get_alfred_sub_bind`].
+/// The broker can be embedded in any process; binding port 0 picks ephemeral ports, e.g. in tests.
+/// It also publishes `module.online` and `module.offline` when modules announce themselves
+/// (module info or heartbeat), disconnect or stop sending heartbeats for `heartbeat_timeout` ms.
+/// # Examples
+/// ```rust
+/// use alfred_core::broker::Broker;
+/// use alfred_core::config::Config;
+/// use alfred_core::connection::Connection;
+/// use alfred_core::message::Message;
+///
+/// # tokio::runtime::Runtime::new().unwrap().block_on(async {
+/// let mut config = Config::default();
+/// config.alfred.pub_port = 0;
+/// config.alfred.sub_port = 0;
+/// let broker = Broker::bind(&config).await.unwrap();
+/// config.alfred.pub_url = Some(broker.pub_url());
+/// config.alfred.sub_url = Some(broker.sub_url());
+/// broker.spawn();
+///
+/// let subscriber = Connection::new(&config).await.unwrap();
+/// subscriber.listen("greetings").await.unwrap();
+/// let publisher = Connection::new(&config).await.unwrap();
+/// publisher.send("greetings", &Message { text: "hello".to_string(), ..Message::default() }).await.unwrap();
+/// let (topic, message) = subscriber.receive_all().await.unwrap();
+/// assert_eq!(topic, "greetings");
+/// assert_eq!(message.text, "hello");
+/// # });
+/// ```
+pub struct Broker {
+    publishers: Vec<(Endpoint, Listener)>,
+    subscribers: Vec<(Endpoint, Listener)>,
+    codec: CodecKind,
+    unrouted: UnroutedPolicy,
+    heartbeat_timeout: Duration,
+    handshake_timeout: Duration
+}
+
+impl Broker {
+    /// Binds every endpoint, failing on the first one that is invalid or cannot be bound.
+    pub async fn bind(config: &Config) -> Result<Self, Error> {
+        let publishers = bind_all(&config.get_alfred_pub_bind(), "publish").await?;
+        let subscribers = bind_all(&config.get_alfred_sub_bind(), "subscription").await?;
+        Ok(Self {
+            publishers,
+            subscribers,
+            codec: config.alfred.codec,
+            unrouted: config.alfred.unrouted,
+            heartbeat_timeout: Duration::from_millis(config.alfred.heartbeat_timeout),
+            handshake_timeout: Duration::from_millis(config.alfred.handshake_timeout)
+        })
+    }
+
+    /// URL the modules publish to (the first bound endpoint).
+    pub fn pub_url(&self) -> String {
+        self.pub_urls().into_iter().next().unwrap_or_default()
+    }
+
+    /// URL the modules subscribe to (the first bound endpoint).
+    pub fn sub_url(&self) -> String {
+        self.sub_urls().into_iter().next().unwrap_or_default()
+    }
+
+    /// URLs the modules can publish to.
+    pub fn pub_urls(&self) -> Vec<String> {
+        self.publishers.iter().map(|(endpoint, _)| endpoint.connect_url()).collect()
+    }
+
+    /// URLs the modules can subscribe to.
+    pub fn sub_urls(&self) -> Vec<String> {
+        self.subscribers.iter().map(|(endpoint, _)| endpoint.connect_url()).collect()
+    }
+
+    /// Forwards messages forever: errors accepting connections are logged and retried, and a failing
+    /// connection is closed alone. Only fails when the broker cannot start, e.g. on an unknown codec.
+    pub async fn run(self) -> Result<(), Error> {
+        self.run_until(std::future::pending()).await
+    }
+
+    /// Forwards messages until `shutdown` completes, then closes the listeners and every connection.
+    pub async fn run_until(self, shutdown: impl Future<Output = ()>) -> Result<(), Error> {
+        let router = Router::new(self.codec.codec()?, self.unrouted, self.handshake_timeout);
+        let (accepted_sender, mut accepted) = mpsc::unbounded_channel();
+        // listeners and connections share the set, so stopping the broker stops all of them
+        let mut tasks = JoinSet::new();
+        for (_, listener) in self.publishers {
+            tasks.spawn(accept(listener, Side::Publish, accepted_sender.clone()));
+        }
+        for (_, listener) in self.subscribers {
+            tasks.spawn(accept(listener, Side::Subscribe, accepted_sender.clone()));
+        }
+        if !self.heartbeat_timeout.is_zero() {
+            tasks.spawn(expire_modules(router.clone(), self.heartbeat_timeout));
+        }
+        tokio::pin!(shutdown);
+        let result = loop {
+            tokio::select! {
+                () = &mut shutdown => break Ok(()),
+                Some((stream, address, side)) = accepted.recv() => {
+                    tasks.spawn(serve(stream, address, side, router.clone()));
+                },
+                Some(result) = tasks.join_next() => match result {
+                    Ok(Ok(())) => {},
+                    Ok(Err(error)) => break Err(error),
+                    Err(error) => error!("Broker task failed: {error}")
+                }
+            }
+        };
+        tasks.shutdown().await;
+        result
+    }
+
+    /// Runs the broker on a new tokio task, until [`BrokerHandle::stop`].
+    pub fn spawn(self) -> BrokerHandle {
+        let (stop, stopped) = oneshot::channel();
+        let task = tokio::spawn(self.run_until(async move {
+            // a dropped handle leaves the broker running
+            if stopped.await.is_err() { std::future::pending::<()>().await; }
+        }));
+        BrokerHandle { stop, task }
+    }
+}
+
+/// Broker running on a tokio task, see [`Broker::spawn`]. Dropping the handle leaves the broker running.
+pub struct BrokerHandle {
+    stop: oneshot::Sender<()>,
+    task: JoinHandle<Result<(), Error>>
+}
+
+impl BrokerHandle {
+    /// Stops the broker, returning once its listeners and connections are closed.
+    pub async fn stop(self) -> Result<(), Error> {
+        let _ = self.stop.send(());
+        self.task.await.map_err(|_| Error::ConnectionError)?
+    }
+
+    /// Waits for the broker to end, which only happens once stopped or when it cannot start.
+    pub async fn join(self) -> Result<(), Error> {
+        self.task.await.map_err(|_| Error::ConnectionError)?
+    }
+}
+
+async fn bind_all(urls: &[String], side: &str) -> Result<Vec<(Endpoint, Listener)>, Error> {
+    let mut listeners = Vec::with_capacity(urls.len());
+    for url in urls {
+        let endpoint: Endpoint = url.parse()?;
+        info!("Binding {endpoint} for {side}...");
+        let listener = Listener::bind(&endpoint).await?;
+        listeners.push((listener.endpoint()?, listener));
+    }
+    Ok(listeners)
+}
+
+/// Side of the broker a connection was accepted on.
+#[derive(Clone, Copy)]
+enum Side {
+    Publish,
+    Subscribe
+}
+
+type Accepted = (Box<dyn Stream>, String, Side);
+
+async fn accept(listener: Listener, side: Side, accepted: mpsc::UnboundedSender<Accepted>) -> Result<(), Error> {
+    loop {
+        // errors like a lack of file descriptors are transient: the listener is kept
+        let (stream, address) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                warn!("Unable to accept a connection: {e}");
+                tokio::time::sleep(ACCEPT_ERROR_DELAY).await;
+                continue;
+            }
+        };
+        debug!("New connection from {address}");
+        if accepted.send((stream, address, side)).is_err() { return Ok(()); }
+    }
+}
+
+async fn serve(stream: Box<dyn Stream>, address: String, side: Side, router: Router) -> Result<(), Error> {
+    let result = match side {
+        Side::Publish => handle_publisher(stream, router).await,
+        Side::Subscribe => handle_subscriber(stream, router).await
+    };
+    match result {
+        Ok(()) => debug!("Connection from {address} closed"),
+        Err(error) => warn!("Connection from {address} closed: {error}")
+    }
+    Ok(())
+}
+
+/// Task writing to a subscriber, stopped with its connection.
+struct SubscriberWriter(JoinHandle<()>);
+
+impl Drop for SubscriberWriter {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// Publishes `module.offline` for the modules that stopped sending heartbeats.
+async fn expire_modules(router: Router, timeout: Duration) -> Result<(), Error> {
+    let mut interval = tokio::time::interval((timeout / 4).max(Duration::from_millis(1)));
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+        router.expire_modules(timeout).await;
+    }
+}
+
+fn check_peer(peer_type: &str, compatible: [&str; 2]) -> Result<(), Error> {
+    if compatible.contains(&peer_type) { return Ok(()); }
+    Err(Error::ProtocolError(format!("incompatible socket type {peer_type}")))
+}
+
+async fn handle_publisher<S: AsyncRead + AsyncWrite + Unpin>(mut stream: S, router: Router) -> Result<(), Error> {
+    check_peer(&zmtp::handshake(&mut stream, "XSUB", router.handshake_timeout).await?, ["PUB", "XPUB"])?;
+    zmtp::write_message(&mut stream, &[Bytes::from_static(&[SUBSCRIBE])]).await?;
+    let id = router.add_publisher();
+    let mut reader = BufReader::new(stream);
+    let result = loop {
+        match zmtp::read_message(&mut reader).await {
+            Ok(Some(frames)) => router.route(id, frames).await,
+            Ok(None) => break Ok(()),
+            Err(error) => break Err(error)
+        }
+    };
+    router.remove_publisher(id).await;
+    result
+}
+
+async fn handle_subscriber<S: AsyncRead + AsyncWrite + Send + Unpin + 'static>(mut stream: S, router: Router) -> Result<(), Error> {
+    check_peer(&zmtp::handshake(&mut stream, "XPUB", router.handshake_timeout).await?, ["SUB", "XSUB"])?;
+    let (reader, writer) = tokio::io::split(stream);
+    let (sender, mut receiver) = mpsc::channel::<Frames>(SUBSCRIBER_QUEUE_SIZE);
+    let id = router.add(sender).await;
+    let _writer = SubscriberWriter(tokio::spawn(async move {
+        let mut writer = BufWriter::new(writer);
+        while let Some(frames) = receiver.recv().await {
+            if zmtp::write_message(&mut writer, &frames).await.is_err() { break; }
+        }
+    }));
+    let mut reader = BufReader::new(reader);
+    let result = loop {
+        match zmtp::read_message(&mut reader).await {
+            Ok(Some(frames)) => router.update_subscription(id, &frames).await,
+            Ok(None) => break Ok(()),
+            Err(error) => break Err(error)
+        }
+    };
+    router.remove(id).await;
+    result
+}
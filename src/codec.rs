@@ -0,0 +1,185 @@
+use std::fmt;
+use std::str::FromStr;
+use serde_derive::Deserialize;
+use crate::error::MessageCompressionError;
+use crate::message::Message;
+
+/// First byte of a JSON frame.
+/// Codec markers never start a valid UTF-8 sequence (v1 frames) nor a versioned native frame.
+pub const JSON_MARKER: u8 = 0xF5;
+/// First byte of a `MessagePack` frame.
+pub const MESSAGE_PACK_MARKER: u8 = 0xF6;
+
+pub trait MessageCodec: Send + Sync {
+    fn kind(&self) -> CodecKind;
+    fn encode(&self, message: &Message) -> Result<Vec<u8>, MessageCompressionError>;
+    fn decode(&self, frame: &[u8]) -> Result<Message, MessageCompressionError>;
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum CodecKind {
+    #[default]
+    Native,
+    Json,
+    #[serde(rename = "msgpack")]
+    MessagePack
+}
+
+impl CodecKind {
+    /// Returns the codec which encoded the given frame.
+    pub const fn from_frame(frame: &[u8]) -> Self {
+        match frame.first() {
+            Some(&JSON_MARKER) => Self::Json,
+            Some(&MESSAGE_PACK_MARKER) => Self::MessagePack,
+            _ => Self::Native
+        }
+    }
+
+    pub fn codec(self) -> Result<Box<dyn MessageCodec>, MessageCompressionError> {
+        match self {
+            Self::Native => Ok(Box::new(NativeCodec)),
+            #[cfg(feature = "json")]
+            Self::Json => Ok(Box::new(JsonCodec)),
+            #[cfg(feature = "msgpack")]
+            Self::MessagePack => Ok(Box::new(MessagePackCodec)),
+            #[allow(unreachable_patterns)]
+            kind => Err(MessageCompressionError::UnsupportedCodec(kind.to_string()))
+        }
+    }
+}
+
+impl FromStr for CodecKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "native" => Self::Native,
+            "json" => Self::Json,
+            "msgpack" => Self::MessagePack,
+            _ => Err(format!("{s} is not a valid codec."))?
+        })
+    }
+}
+
+impl fmt::Display for CodecKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", match self {
+            Self::Native => "native",
+            Self::Json => "json",
+            Self::MessagePack => "msgpack"
+        })
+    }
+}
+
+/// Decodes a frame written by any codec, looking at its marker.
+/// # Examples
+/// ```rust
+/// use alfred_core::codec::{decode, CodecKind};
+/// use alfred_core::message::{Message, MessageType};
+///
+/// let message = Message { text: String::from("hello"), message_type: MessageType::Text, ..Message::default() };
+/// let frame = CodecKind::Native.codec().unwrap().encode(&message).unwrap();
+/// assert_eq!(decode(&frame).unwrap(), message);
+/// ```
+pub fn decode(frame: &[u8]) -> Result<Message, MessageCompressionError> {
+    CodecKind::from_frame(frame).codec()?.decode(frame)
+}
+
+/// The length-prefixed format of [`Message::compress`].
+pub struct NativeCodec;
+
+impl MessageCodec for NativeCodec {
+    fn kind(&self) -> CodecKind {
+        CodecKind::Native
+    }
+
+    fn encode(&self, message: &Message) -> Result<Vec<u8>, MessageCompressionError> {
+        message.try_compress()
+    }
+
+    fn decode(&self, frame: &[u8]) -> Result<Message, MessageCompressionError> {
+        Message::decompress(frame)
+    }
+}
+
+#[cfg(any(feature = "json", feature = "msgpack"))]
+fn strip_marker(frame: &[u8], marker: u8, kind: CodecKind) -> Result<&[u8], MessageCompressionError> {
+    match frame.split_first() {
+        Some((&first, payload)) if first == marker => Ok(payload),
+        _ => Err(MessageCompressionError::CodecError(format!("missing {kind} marker")))
+    }
+}
+
+/// JSON codec, handy for modules not written in Rust.
+/// # Examples
+/// ```rust
+/// use alfred_core::codec::{JsonCodec, MessageCodec, JSON_MARKER};
+/// use alfred_core::message::{Message, MessageType};
+///
+/// let message = Message { text: String::from("hello"), message_type: MessageType::Text, ..Message::default() };
+/// let frame = JsonCodec.encode(&message).unwrap();
+/// assert_eq!(frame[0], JSON_MARKER);
+/// assert_eq!(JsonCodec.decode(&frame).unwrap(), message);
+/// assert_eq!(alfred_core::codec::decode(&frame).unwrap(), message);
+/// // missing fields take their default value
+/// let frame = [&[JSON_MARKER][..], br#"{"message_type":"Text","text":"hello"}"#].concat();
+/// assert_eq!(JsonCodec.decode(&frame).unwrap(), message);
+/// ```
+#[cfg(feature = "json")]
+pub struct JsonCodec;
+
+#[cfg(feature = "json")]
+impl MessageCodec for JsonCodec {
+    fn kind(&self) -> CodecKind {
+        CodecKind::Json
+    }
+
+    fn encode(&self, message: &Message) -> Result<Vec<u8>, MessageCompressionError> {
+        message.message_type.check_encodable()?;
+        let mut frame = vec![JSON_MARKER];
+        serde_json::to_writer(&mut frame, message)
+            .map_err(|e| MessageCompressionError::CodecError(e.to_string()))?;
+        Ok(frame)
+    }
+
+    fn decode(&self, frame: &[u8]) -> Result<Message, MessageCompressionError> {
+        serde_json::from_slice(strip_marker(frame, JSON_MARKER, self.kind())?)
+            .map_err(|e| MessageCompressionError::CodecError(e.to_string()))
+    }
+}
+
+/// `MessagePack` codec: compact like the native format, with libraries for most languages.
+/// # Examples
+/// ```rust
+/// use alfred_core::bytes::Bytes;
+/// use alfred_core::codec::{MessagePackCodec, MessageCodec, MESSAGE_PACK_MARKER};
+/// use alfred_core::message::{Message, MessageType};
+///
+/// let message = Message { message_type: MessageType::Audio, data: Bytes::from_static(&[0, 1, 2]), ..Message::default() };
+/// let frame = MessagePackCodec.encode(&message).unwrap();
+/// assert_eq!(frame[0], MESSAGE_PACK_MARKER);
+/// assert_eq!(MessagePackCodec.decode(&frame).unwrap(), message);
+/// ```
+#[cfg(feature = "msgpack")]
+pub struct MessagePackCodec;
+
+#[cfg(feature = "msgpack")]
+impl MessageCodec for MessagePackCodec {
+    fn kind(&self) -> CodecKind {
+        CodecKind::MessagePack
+    }
+
+    fn encode(&self, message: &Message) -> Result<Vec<u8>, MessageCompressionError> {
+        message.message_type.check_encodable()?;
+        let mut frame = vec![MESSAGE_PACK_MARKER];
+        rmp_serde::encode::write_named(&mut frame, message)
+            .map_err(|e| MessageCompressionError::CodecError(e.to_string()))?;
+        Ok(frame)
+    }
+
+    fn decode(&self, frame: &[u8]) -> Result<Message, MessageCompressionError> {
+        rmp_serde::from_slice(strip_marker(frame, MESSAGE_PACK_MARKER, self.kind())?)
+            .map_err(|e| MessageCompressionError::CodecError(e.to_string()))
+    }
+}
@@ -1,15 +1,31 @@
 use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
 use serde_derive::Deserialize;
 use std::fs;
 use std::path::Path;
+use std::str::FromStr;
 use toml;
 use envconfig::Envconfig;
 use toml::{Table, Value};
+use crate::codec::CodecKind;
 
 pub const CONFIG_FILENAME: &str = "config.toml";
+const DEFAULT_URL: &str = "tcp://127.0.0.1";
+const DEFAULT_PUB_PORT: u32 = 5678;
+const DEFAULT_SUB_PORT: u32 = 1234;
 const DEFAULT_TMP_DIR: &str = "/tmp";
+const DEFAULT_HANDSHAKE_TIMEOUT: u64 = 5000;
+const DEFAULT_PROBE_INTERVAL: u64 = 5000;
+const DEFAULT_RECONNECT_DELAY: u64 = 500;
+const DEFAULT_RECONNECT_MAX_DELAY: u64 = 30000;
+const DEFAULT_HEARTBEAT_INTERVAL: u64 = 5000;
+const DEFAULT_HEARTBEAT_TIMEOUT: u64 = 15000;
+const DEFAULT_ACK_TIMEOUT: u64 = 1000;
+const DEFAULT_ACK_RETRIES: u32 = 3;
 
-#[derive(Deserialize, Debug)]
+/// The default configuration does not need any config file, e.g. for modules running on a
+/// [`crate::memory::MemoryBus`].
+#[derive(Deserialize, Debug, Default)]
 pub struct Config {
     pub alfred: AlfredConfig,
     module: HashMap<String, String>
@@ -29,27 +45,89 @@ impl Config {
             .and_then(Value::as_table)
             .map_or_else(HashMap::new, |module_config| module_config
                 .iter()
-                .map(|(k, v)| (k.to_string(), v.as_str().unwrap_or("").to_string()))
+                .map(|(k, v)| (k.clone(), v.as_str().unwrap_or("").to_string()))
                 .collect())
     }
 
     fn read_alfred_config() -> AlfredConfig {
         let from_env = EnvConfig::from_env();
         let from_file_config = FromFileConfig::read();
-        let url = from_env.alfred.url.unwrap_or(from_file_config.alfred.url);
-        let pub_port = from_env.alfred.pub_port.unwrap_or(from_file_config.alfred.pub_port);
-        let sub_port = from_env.alfred.sub_port.unwrap_or(from_file_config.alfred.sub_port);
+        let url = from_env.alfred.url
+            .or(from_file_config.alfred.url)
+            .unwrap_or_else(|| DEFAULT_URL.to_string());
+        let pub_port = from_env.alfred.pub_port.or(from_file_config.alfred.pub_port).unwrap_or(DEFAULT_PUB_PORT);
+        let sub_port = from_env.alfred.sub_port.or(from_file_config.alfred.sub_port).unwrap_or(DEFAULT_SUB_PORT);
+        let pub_url = from_env.alfred.pub_url.or(from_file_config.alfred.pub_url);
+        let sub_url = from_env.alfred.sub_url.or(from_file_config.alfred.sub_url);
+        let pub_bind = from_env.alfred.pub_bind
+            .map(|endpoints| Self::split_list(&endpoints))
+            .or(from_file_config.alfred.pub_bind)
+            .unwrap_or_default();
+        let sub_bind = from_env.alfred.sub_bind
+            .map(|endpoints| Self::split_list(&endpoints))
+            .or(from_file_config.alfred.sub_bind)
+            .unwrap_or_default();
         let tmp_dir = from_env.alfred.tmp_dir
             .or(from_file_config.alfred.tmp_dir)
             .unwrap_or_else(|| DEFAULT_TMP_DIR.to_string());
-        AlfredConfig { url, pub_port, sub_port, tmp_dir, modules: from_file_config.alfred.modules }
+        let codec = from_env.alfred.codec.or(from_file_config.alfred.codec).unwrap_or_default();
+        let unrouted = from_env.alfred.unrouted.or(from_file_config.alfred.unrouted).unwrap_or_default();
+        let handshake_timeout = from_env.alfred.handshake_timeout
+            .or(from_file_config.alfred.handshake_timeout)
+            .unwrap_or(DEFAULT_HANDSHAKE_TIMEOUT);
+        let probe_interval = from_env.alfred.probe_interval
+            .or(from_file_config.alfred.probe_interval)
+            .unwrap_or(DEFAULT_PROBE_INTERVAL);
+        let reconnect_delay = from_env.alfred.reconnect_delay
+            .or(from_file_config.alfred.reconnect_delay)
+            .unwrap_or(DEFAULT_RECONNECT_DELAY);
+        let reconnect_max_delay = from_env.alfred.reconnect_max_delay
+            .or(from_file_config.alfred.reconnect_max_delay)
+            .unwrap_or(DEFAULT_RECONNECT_MAX_DELAY);
+        let heartbeat_interval = from_env.alfred.heartbeat_interval
+            .or(from_file_config.alfred.heartbeat_interval)
+            .unwrap_or(DEFAULT_HEARTBEAT_INTERVAL);
+        let heartbeat_timeout = from_env.alfred.heartbeat_timeout
+            .or(from_file_config.alfred.heartbeat_timeout)
+            .unwrap_or(DEFAULT_HEARTBEAT_TIMEOUT);
+        let ack_timeout = from_env.alfred.ack_timeout
+            .or(from_file_config.alfred.ack_timeout)
+            .unwrap_or(DEFAULT_ACK_TIMEOUT);
+        let ack_retries = from_env.alfred.ack_retries
+            .or(from_file_config.alfred.ack_retries)
+            .unwrap_or(DEFAULT_ACK_RETRIES);
+        let strict_receive = from_env.alfred.strict_receive.or(from_file_config.alfred.strict_receive).unwrap_or_default();
+        AlfredConfig {
+            url, pub_port, sub_port, pub_url, sub_url, pub_bind, sub_bind, tmp_dir, codec, unrouted,
+            handshake_timeout, probe_interval, reconnect_delay, reconnect_max_delay,
+            heartbeat_interval, heartbeat_timeout, ack_timeout, ack_retries, strict_receive,
+            modules: from_file_config.alfred.modules
+        }
+    }
+
+    fn split_list(list: &str) -> Vec<String> {
+        list.split(',').map(str::trim).filter(|item| !item.is_empty()).map(String::from).collect()
     }
 
+    /// Endpoints bound by the broker for the publishers: `pub_bind`, or `pub_url`,
+    /// or every interface on `pub_port`.
+    pub fn get_alfred_pub_bind(&self) -> Vec<String> {
+        Self::get_bind(&self.alfred.pub_bind, self.alfred.pub_url.as_ref(), self.alfred.pub_port)
+    }
+    /// Endpoints bound by the broker for the subscribers: `sub_bind`, or `sub_url`,
+    /// or every interface on `sub_port`.
+    pub fn get_alfred_sub_bind(&self) -> Vec<String> {
+        Self::get_bind(&self.alfred.sub_bind, self.alfred.sub_url.as_ref(), self.alfred.sub_port)
+    }
+    fn get_bind(bind: &[String], url: Option<&String>, port: u32) -> Vec<String> {
+        if !bind.is_empty() { return bind.to_vec(); }
+        vec![url.cloned().unwrap_or_else(|| format!("tcp://*:{port}"))]
+    }
     pub fn get_alfred_pub_url(&self) -> String {
-        format!("{}:{}", self.alfred.url, self.alfred.pub_port)
+        self.alfred.pub_url.clone().unwrap_or_else(|| format!("{}:{}", self.alfred.url, self.alfred.pub_port))
     }
     pub fn get_alfred_sub_url(&self) -> String {
-        format!("{}:{}", self.alfred.url, self.alfred.sub_port)
+        self.alfred.sub_url.clone().unwrap_or_else(|| format!("{}:{}", self.alfred.url, self.alfred.sub_port))
     }
     pub fn get_module_value(&self, key: &str) -> Option<String> {
         self.module.get(key).cloned()
@@ -61,10 +139,103 @@ pub struct AlfredConfig {
     pub url: String,
     pub pub_port: u32,
     pub sub_port: u32,
+    /// Full endpoint (e.g. `ipc:///run/alfred/pub.sock`) used instead of `url` and `pub_port`
+    pub pub_url: Option<String>,
+    /// Full endpoint (e.g. `ipc:///run/alfred/sub.sock`) used instead of `url` and `sub_port`
+    pub sub_url: Option<String>,
+    /// Endpoints bound by the broker for the publishers (see [`Config::get_alfred_pub_bind`])
+    pub pub_bind: Vec<String>,
+    /// Endpoints bound by the broker for the subscribers (see [`Config::get_alfred_sub_bind`])
+    pub sub_bind: Vec<String>,
     pub tmp_dir: String,
+    pub codec: CodecKind,
+    /// What the broker does with messages published on topics without subscribers
+    pub unrouted: UnroutedPolicy,
+    /// Maximum time (ms) to wait for the handshake with the broker once the sockets are connected;
+    /// the broker closes the connections which do not complete their handshake in time
+    pub handshake_timeout: u64,
+    /// Interval (ms) between two broker liveness probes; 0 disables them
+    pub probe_interval: u64,
+    /// First delay (ms) between two reconnection attempts, doubled after each failure
+    pub reconnect_delay: u64,
+    /// Maximum delay (ms) between two reconnection attempts
+    pub reconnect_max_delay: u64,
+    /// Interval (ms) between two heartbeats sent by the modules; 0 disables them
+    pub heartbeat_interval: u64,
+    /// Time (ms) without heartbeat after which the broker considers a module offline; 0 disables the check
+    pub heartbeat_timeout: u64,
+    /// Malformed messages make `receive` fail instead of being skipped and published on the dead-letter topic
+    pub strict_receive: bool,
+    /// Time (ms) to wait for the acknowledgement of a reliable message before sending it again,
+    /// doubled after each attempt
+    pub ack_timeout: u64,
+    /// Number of times a reliable message is sent again before giving up
+    pub ack_retries: u32,
     pub modules: Vec<String>
 }
 
+impl Default for AlfredConfig {
+    fn default() -> Self {
+        Self {
+            url: DEFAULT_URL.to_string(),
+            pub_port: DEFAULT_PUB_PORT,
+            sub_port: DEFAULT_SUB_PORT,
+            pub_url: None,
+            sub_url: None,
+            pub_bind: Vec::new(),
+            sub_bind: Vec::new(),
+            tmp_dir: DEFAULT_TMP_DIR.to_string(),
+            codec: CodecKind::default(),
+            unrouted: UnroutedPolicy::default(),
+            handshake_timeout: DEFAULT_HANDSHAKE_TIMEOUT,
+            probe_interval: DEFAULT_PROBE_INTERVAL,
+            reconnect_delay: DEFAULT_RECONNECT_DELAY,
+            reconnect_max_delay: DEFAULT_RECONNECT_MAX_DELAY,
+            heartbeat_interval: DEFAULT_HEARTBEAT_INTERVAL,
+            heartbeat_timeout: DEFAULT_HEARTBEAT_TIMEOUT,
+            strict_receive: false,
+            ack_timeout: DEFAULT_ACK_TIMEOUT,
+            ack_retries: DEFAULT_ACK_RETRIES,
+            modules: Vec::new()
+        }
+    }
+}
+
+/// What the broker does with a message published on a topic nobody listens to.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum UnroutedPolicy {
+    #[default]
+    Ignore,
+    /// Logs a warning
+    Log,
+    /// Logs a warning and publishes it on [`crate::broker::UNROUTED_TOPIC`]
+    Publish
+}
+
+impl FromStr for UnroutedPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "ignore" => Self::Ignore,
+            "log" => Self::Log,
+            "publish" => Self::Publish,
+            _ => Err(format!("{s} is not a valid unrouted policy."))?
+        })
+    }
+}
+
+impl Display for UnroutedPolicy {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", match self {
+            Self::Ignore => "ignore",
+            Self::Log => "log",
+            Self::Publish => "publish"
+        })
+    }
+}
+
 #[derive(Deserialize, Debug)]
 struct FromFileConfig {
     alfred: FromFileAlfredConfig
@@ -87,10 +258,25 @@ impl FromFileConfig {
 
 #[derive(Deserialize, Debug)]
 struct FromFileAlfredConfig {
-    url: String,
-    pub_port: u32,
-    sub_port: u32,
+    url: Option<String>,
+    pub_port: Option<u32>,
+    sub_port: Option<u32>,
+    pub_url: Option<String>,
+    sub_url: Option<String>,
+    pub_bind: Option<Vec<String>>,
+    sub_bind: Option<Vec<String>>,
     tmp_dir: Option<String>,
+    codec: Option<CodecKind>,
+    unrouted: Option<UnroutedPolicy>,
+    handshake_timeout: Option<u64>,
+    probe_interval: Option<u64>,
+    reconnect_delay: Option<u64>,
+    reconnect_max_delay: Option<u64>,
+    heartbeat_interval: Option<u64>,
+    heartbeat_timeout: Option<u64>,
+    strict_receive: Option<bool>,
+    ack_timeout: Option<u64>,
+    ack_retries: Option<u32>,
     #[serde(default)]
     modules: Vec<String>
 }
@@ -116,6 +302,38 @@ struct EnvAlfredConfig {
     pub_port: Option<u32>,
     #[envconfig(from = "ALFRED_SUB_PORT")]
     sub_port: Option<u32>,
+    #[envconfig(from = "ALFRED_PUB_URL")]
+    pub_url: Option<String>,
+    #[envconfig(from = "ALFRED_SUB_URL")]
+    sub_url: Option<String>,
+    /// Comma-separated list of endpoints
+    #[envconfig(from = "ALFRED_PUB_BIND")]
+    pub_bind: Option<String>,
+    /// Comma-separated list of endpoints
+    #[envconfig(from = "ALFRED_SUB_BIND")]
+    sub_bind: Option<String>,
     #[envconfig(from = "ALFRED_TMP_DIR")]
-    tmp_dir: Option<String>
+    tmp_dir: Option<String>,
+    #[envconfig(from = "ALFRED_CODEC")]
+    codec: Option<CodecKind>,
+    #[envconfig(from = "ALFRED_UNROUTED")]
+    unrouted: Option<UnroutedPolicy>,
+    #[envconfig(from = "ALFRED_HANDSHAKE_TIMEOUT")]
+    handshake_timeout: Option<u64>,
+    #[envconfig(from = "ALFRED_PROBE_INTERVAL")]
+    probe_interval: Option<u64>,
+    #[envconfig(from = "ALFRED_RECONNECT_DELAY")]
+    reconnect_delay: Option<u64>,
+    #[envconfig(from = "ALFRED_RECONNECT_MAX_DELAY")]
+    reconnect_max_delay: Option<u64>,
+    #[envconfig(from = "ALFRED_HEARTBEAT_INTERVAL")]
+    heartbeat_interval: Option<u64>,
+    #[envconfig(from = "ALFRED_HEARTBEAT_TIMEOUT")]
+    heartbeat_timeout: Option<u64>,
+    #[envconfig(from = "ALFRED_STRICT_RECEIVE")]
+    strict_receive: Option<bool>,
+    #[envconfig(from = "ALFRED_ACK_TIMEOUT")]
+    ack_timeout: Option<u64>,
+    #[envconfig(from = "ALFRED_ACK_RETRIES")]
+    ack_retries: Option<u32>
 }
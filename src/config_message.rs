@@ -19,6 +19,11 @@ impl ConfigMessage {
             sender: self.sender.clone().unwrap_or_else(|| default.sender.clone()),
             message_type: self.message_type.clone().unwrap_or_else(|| default.message_type.clone()),
             params: default.params.clone(),
+            data: default.data.clone(),
+            id: default.id.clone(),
+            correlation_id: default.correlation_id.clone(),
+            timestamp: default.timestamp,
+            hops: default.hops,
         }
     }
 }
\ No newline at end of file
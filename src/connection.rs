@@ -1,67 +1,213 @@
 use std::collections::BTreeMap;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::time::Duration;
+use bytes::Bytes;
+use crate::broker::SUBSCRIPTIONS_TOPIC_REQUEST;
 use crate::config::Config;
-use crate::message::{Message, MessageType};
+use crate::dedup::RecentIds;
+use crate::dead_letter::{self, DeadLetter, DEAD_LETTER_TOPIC};
+use crate::message::Message;
 use crate::error::Error;
-use log::debug;
-use tokio::sync::Mutex;
-use crate::zmq_connection::{AlfredPublisher, AlfredSubscriber};
+use crate::module_info::ModuleInfo;
+use log::{debug, warn};
+use tokio::sync::{watch, Mutex};
+use crate::receiver::Receiver;
+use crate::supervisor::{self, Supervisor};
+use crate::transport::{Publisher, Transport, ZmqTransport};
 
 pub const MODULE_INFO_TOPIC_REQUEST: &str = "module.info.request";
 pub const MODULE_INFO_TOPIC_RESPONSE: &str = "module.info.response";
+/// Topic of the heartbeats periodically sent by the modules.
+pub const MODULE_HEARTBEAT_TOPIC: &str = "module.heartbeat";
+/// Published by the broker when a module shows up (module name in the text).
+pub const MODULE_ONLINE_TOPIC: &str = "module.online";
+/// Published by the broker when a module disconnects or stops sending heartbeats (module name in the text).
+pub const MODULE_OFFLINE_TOPIC: &str = "module.offline";
 pub const TOPIC_PREFIX: &str = "event";
+/// Prefixes of the topics private to a connection (`<prefix>.<id>`), in the library namespace
+/// so that application topics like `reply.status` are never mistaken for them.
+pub const REPLY_TOPIC_PREFIX: &str = "alfred.reply";
+pub const PROBE_TOPIC_PREFIX: &str = "alfred.probe";
+pub const ACK_TOPIC_PREFIX: &str = "alfred.ack";
+/// Param of a reliable message with the topic its acknowledgement is expected on.
+pub const ACK_TOPIC_PARAM: &str = "alfred.ack";
+/// Reliable messages received, remembered to drop their copies.
+const RECENT_IDS_CAPACITY: usize = 1024;
+pub(crate) const HANDSHAKE_PROBE_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Whether `topic` is the topic of a connection probe (`alfred.probe.<id>`), not an application topic like `probes`.
+pub(crate) fn is_probe_topic(topic: &[u8]) -> bool {
+    is_private_topic(topic, PROBE_TOPIC_PREFIX)
+}
+
+/// Whether `topic` is a topic private to a connection (`<prefix>.<id>`), e.g. the reply topic of a request.
+fn is_private_topic(topic: &[u8], prefix: &str) -> bool {
+    topic.strip_prefix(prefix.as_bytes()).is_some_and(|rest| rest.starts_with(b"."))
+}
+
+/// State of the link with the broker, as seen by the liveness probe.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConnectionStatus {
+    Connected,
+    Disconnected
+}
 
 #[derive(Clone)]
 pub struct Connection {
-    subscriber: Arc<Mutex<AlfredSubscriber>>,
-    publisher: Arc<Mutex<AlfredPublisher>>
+    receiver: Arc<Receiver>,
+    publisher: Arc<Mutex<Box<dyn Publisher>>>,
+    status: watch::Receiver<ConnectionStatus>,
+    strict_receive: bool,
+    malformed: Arc<AtomicU64>,
+    ack_timeout: Duration,
+    ack_retries: u32,
+    recent_ids: Arc<Mutex<RecentIds>>
 }
 
 impl Connection {
     pub async fn new(config: &Config) -> Result<Self, Error> {
-        let subscriber = AlfredSubscriber::new(config.get_alfred_sub_url().as_str()).await?;
-        debug!("Connected as subscriber");
-        tokio::time::sleep(Duration::from_secs(1)).await;
-        let publisher = AlfredPublisher::new(config.get_alfred_pub_url().as_str()).await?;
-        debug!("Connected as publisher");
-        let mut connection = Self {
-            subscriber: Arc::new(Mutex::new(subscriber)),
-            publisher: Arc::new(Mutex::new(publisher))
+        Self::with_transport(config, Arc::new(ZmqTransport::new(config))).await
+    }
+
+    /// Creates a connection on a custom transport, e.g. a [`crate::memory::MemoryBus`] in tests.
+    pub async fn with_transport(config: &Config, transport: Arc<dyn Transport>) -> Result<Self, Error> {
+        let (subscriber, publisher) = transport.connect().await?;
+        debug!("Connected");
+        let (status_sender, status) = watch::channel(ConnectionStatus::Connected);
+        let connection = Self {
+            receiver: Arc::new(Receiver::spawn(subscriber)),
+            publisher: Arc::new(Mutex::new(publisher)),
+            status,
+            strict_receive: config.alfred.strict_receive,
+            malformed: Arc::default(),
+            ack_timeout: Duration::from_millis(config.alfred.ack_timeout),
+            ack_retries: config.alfred.ack_retries,
+            recent_ids: Arc::new(Mutex::new(RecentIds::new(RECENT_IDS_CAPACITY)))
         };
         connection.listen(MODULE_INFO_TOPIC_REQUEST).await?;
+        let probe_topic = format!("{PROBE_TOPIC_PREFIX}.{}", Message::new_id());
+        connection.listen(&probe_topic).await?;
+        connection.handshake(&probe_topic, Duration::from_millis(config.alfred.handshake_timeout)).await?;
+        if config.alfred.probe_interval > 0 {
+            Supervisor::spawn(config, &connection.receiver, &connection.publisher, transport, status_sender, probe_topic);
+        }
         Ok(connection)
     }
 
-    pub async fn listen(&mut self, topic: &str) -> Result<(), Error> {
-        self.subscriber.lock().await.listen(topic).await
+    /// Waits until a probe published on `probe_topic` is received back through the broker,
+    /// so both sockets are known to be connected and subscribed before the connection is used.
+    async fn handshake(&self, probe_topic: &str, timeout: Duration) -> Result<(), Error> {
+        let deadline = tokio::time::Instant::now() + timeout;
+        while tokio::time::Instant::now() < deadline {
+            let attempt = tokio::time::Instant::now();
+            if supervisor::probe(&self.receiver, &self.publisher, probe_topic, HANDSHAKE_PROBE_INTERVAL).await {
+                debug!("Handshake with the broker completed");
+                return Ok(());
+            }
+            // a probe which could not even be sent fails at once
+            tokio::time::sleep_until(attempt + HANDSHAKE_PROBE_INTERVAL).await;
+        }
+        Err(Error::HandshakeTimeout)
+    }
+
+    /// Returns a watcher notified each time the broker is lost or the connection is restored.
+    ///
+    /// Subscriptions made with [`Connection::listen`] are replayed automatically after a reconnection.
+    pub fn status(&self) -> watch::Receiver<ConnectionStatus> {
+        self.status.clone()
+    }
+
+    pub async fn listen(&self, topic: &str) -> Result<(), Error> {
+        self.receiver.listen(topic, false).await
     }
 
+    /// Like [`Connection::listen`], without acknowledging the reliable messages received on `topic`,
+    /// for the modules observing the messages sent to others (e.g. the queue bin).
+    pub async fn listen_passive(&self, topic: &str) -> Result<(), Error> {
+        self.receiver.listen(topic, true).await
+    }
+
+    pub async fn unlisten(&self, topic: &str) -> Result<(), Error> {
+        self.receiver.unlisten(topic).await
+    }
+
+    /// Number of malformed messages skipped, see [`Connection::receive_all`].
+    pub fn malformed_count(&self) -> u64 {
+        self.malformed.load(Ordering::Relaxed)
+    }
+
+    /// Waits for the next message.
+    ///
+    /// Messages that cannot be decoded are skipped and published on [`DEAD_LETTER_TOPIC`],
+    /// unless `strict_receive` is set: `Error::MalformedMessage` is then returned.
+    /// Replies to requests and acknowledgements that already ended (see [`Connection::request`]) are dropped.
+    /// Reliable messages (see [`Connection::send_reliable`]) sent by other connections are acknowledged
+    /// if received through a subscription made with [`Connection::listen`], other than the wildcard (the empty topic);
+    /// their copies are dropped.
     pub async fn receive_all(&self) -> Result<(String, Message), Error> {
-        self.subscriber.lock().await.receive().await
+        loop {
+            let (topic, mut message) = match self.receiver.receive().await {
+                Err(Error::MalformedMessage { topic, frames, reason }) if !self.strict_receive => {
+                    self.skip_malformed(&topic, &frames, &reason).await;
+                    continue;
+                },
+                received => received?
+            };
+            if is_probe_topic(topic.as_bytes()) { continue; }
+            // replies and acknowledgements routed to a pending request are never received here:
+            // the others arrived too late, e.g. the acknowledgements of a second receiver
+            if is_private_topic(topic.as_bytes(), REPLY_TOPIC_PREFIX) || is_private_topic(topic.as_bytes(), ACK_TOPIC_PREFIX) {
+                debug!("Dropping message received on topic {topic} after its request ended");
+                continue;
+            }
+            if let Some(ack_topic) = message.params.remove(ACK_TOPIC_PARAM) {
+                // every copy is acknowledged, as the previous acknowledgement may have been lost,
+                // unless the message was sent by this connection or is only observed (e.g. by logs)
+                if !self.receiver.expects_reply(&ack_topic).await && self.receiver.acknowledges(&topic).await {
+                    self.acknowledge(&ack_topic, &message).await;
+                }
+                if !self.recent_ids.lock().await.insert(&message.id) {
+                    debug!("Dropping copy of message {} received on topic {topic}", message.id);
+                    continue;
+                }
+            }
+            return Ok((topic, message));
+        }
     }
 
-    async fn send_module_info(&self, module_name: &str, capabilities: &BTreeMap<String, String>) -> Result<(), Error> {
-        let info_msg = Message {
-            text: module_name.to_string(),
-            message_type: MessageType::Text,
-            params: capabilities.clone(),
-            ..Message::default()
-        };
-        self.send(MODULE_INFO_TOPIC_RESPONSE, &info_msg).await
+    async fn acknowledge(&self, ack_topic: &str, message: &Message) {
+        let ack = Message { correlation_id: message.id.clone(), ..Message::default() };
+        if let Err(e) = self.send(ack_topic, &ack).await {
+            warn!("Unable to acknowledge message {}: {e}", message.id);
+        }
     }
 
-    pub async fn manage_module_info_request(&self, topic: &str, module_name: &str, capabilities: &BTreeMap<String, String>) -> Result<bool, Error> {
+    async fn skip_malformed(&self, topic: &str, frames: &[Bytes], reason: &str) {
+        self.malformed.fetch_add(1, Ordering::Relaxed);
+        warn!("Skipping malformed message on topic {topic}: {reason}");
+        // a malformed dead letter must not be published again
+        if topic == DEAD_LETTER_TOPIC { return; }
+        if let Err(e) = self.send(DEAD_LETTER_TOPIC, &dead_letter::malformed(topic, frames, reason)).await {
+            warn!("Unable to publish the dead letter: {e}");
+        }
+    }
+
+    pub async fn send_module_info(&self, info: &ModuleInfo) -> Result<(), Error> {
+        self.send(MODULE_INFO_TOPIC_RESPONSE, &info.to_message()).await
+    }
+
+    pub async fn manage_module_info_request(&self, topic: &str, info: &ModuleInfo) -> Result<bool, Error> {
         if topic != MODULE_INFO_TOPIC_REQUEST { return Ok(false); }
         debug!("Received info request. Replying...");
-        self.send_module_info(module_name, capabilities).await?;
+        self.send_module_info(info).await?;
         Ok(true)
     }
 
-    pub async fn receive(&self, module_name: &str, capabilities: &BTreeMap<String, String>) -> Result<(String, Message), Error> {
+    pub async fn receive(&self, info: &ModuleInfo) -> Result<(String, Message), Error> {
         loop {
             let (topic, message) = self.receive_all().await?;
-            if self.manage_module_info_request(topic.as_str(), module_name, capabilities).await? {
+            if self.manage_module_info_request(topic.as_str(), info).await? {
                 continue;
             }
             return Ok((topic, message));
@@ -69,7 +215,22 @@ impl Connection {
     }
 
     pub async fn send(&self, topic: &str, message: &Message) -> Result<(), Error> {
-        self.publisher.lock().await.send(topic, message).await
+        if message.is_stamped() {
+            return self.publisher.lock().await.send(topic, message).await;
+        }
+        let mut message = message.clone();
+        message.stamp();
+        self.publisher.lock().await.send(topic, &message).await
+    }
+
+    /// Publishes a message that could not be handled on [`DEAD_LETTER_TOPIC`].
+    /// Dead letters are never published again, to avoid loops.
+    pub async fn send_dead_letter(&self, dead_letter: &DeadLetter) -> Result<(), Error> {
+        if dead_letter.topic == DEAD_LETTER_TOPIC {
+            warn!("Dropping a dead letter that could not be handled: {}", dead_letter.reason);
+            return Ok(());
+        }
+        self.send(DEAD_LETTER_TOPIC, &dead_letter.to_message()?).await
     }
 
     pub async fn send_event(&self, publisher_name: &str, event_name: &str, message: &Message) -> Result<(), Error> {
@@ -77,4 +238,102 @@ impl Connection {
         let topic_ref: &'static str = Box::leak(topic.into_boxed_str());
         self.send(topic_ref, message).await
     }
-}
\ No newline at end of file
+
+    /// Sends `message` to `topic` until it is acknowledged, so it is received at least once.
+    ///
+    /// The acknowledgement is sent by [`Connection::receive_all`] on the receiving side, which also
+    /// drops the copies of a message already received: only modules listening to `topic` (or to one of its
+    /// prefixes) acknowledge it, not the ones observing every message or listening with [`Connection::listen_passive`]. The message is sent again after
+    /// `ack_timeout` ms, doubled at each attempt, up to `ack_retries` times: then
+    /// `Error::AckTimeout` is returned.
+    pub async fn send_reliable(&self, topic: &str, message: &Message) -> Result<(), Error> {
+        let mut message = message.clone();
+        message.stamp();
+        let ack_topic = format!("{ACK_TOPIC_PREFIX}.{}", message.id);
+        message.params.insert(ACK_TOPIC_PARAM.to_string(), ack_topic.clone());
+        let mut ack = self.receiver.expect_reply(&ack_topic).await;
+        if let Err(error) = self.listen(&ack_topic).await {
+            self.receiver.cancel_reply(&ack_topic).await;
+            return Err(error);
+        }
+        let mut timeout = self.ack_timeout;
+        let mut result = Err(Error::AckTimeout(topic.to_string()));
+        for attempt in 0..=self.ack_retries {
+            if attempt > 0 {
+                debug!("Message {} sent to topic {topic} not acknowledged: sending it again", message.id);
+            }
+            if let Err(e) = self.send(topic, &message).await {
+                warn!("Unable to send message {} to topic {topic}: {e}", message.id);
+            }
+            match tokio::time::timeout(timeout, &mut ack).await {
+                Ok(acknowledged) => {
+                    result = acknowledged.map(|_| ()).map_err(|_| Error::ConnectionError);
+                    break;
+                },
+                Err(_) => timeout = timeout.saturating_mul(2)
+            }
+        }
+        self.receiver.cancel_reply(&ack_topic).await;
+        self.unlisten(&ack_topic).await?;
+        result
+    }
+
+    /// Sends `message` to `topic` and waits for its reply.
+    ///
+    /// The reply is expected on a private topic, pushed in front of the response topics,
+    /// so several requests can be in flight at the same time.
+    pub async fn request(&self, topic: &str, message: &Message, timeout: Duration) -> Result<Message, Error> {
+        let mut request = message.clone();
+        request.stamp();
+        let reply_topic = format!("{REPLY_TOPIC_PREFIX}.{}", request.id);
+        request.response_topics.push_front(reply_topic.clone());
+        let reply = self.receiver.expect_reply(&reply_topic).await;
+        if let Err(error) = self.listen(&reply_topic).await {
+            self.receiver.cancel_reply(&reply_topic).await;
+            return Err(error);
+        }
+        let result = match self.send(topic, &request).await {
+            Ok(()) => tokio::time::timeout(timeout, reply).await
+                .map_err(|_| Error::RequestTimeout(topic.to_string()))
+                .and_then(|reply| reply.map_err(|_| Error::ConnectionError)),
+            Err(error) => Err(error)
+        };
+        self.receiver.cancel_reply(&reply_topic).await;
+        self.unlisten(&reply_topic).await?;
+        result
+    }
+
+    /// Asks the broker for the subscribed topic prefixes, with the number of subscribers of each one.
+    pub async fn broker_subscriptions(&self, timeout: Duration) -> Result<BTreeMap<String, usize>, Error> {
+        let reply = self.request(SUBSCRIPTIONS_TOPIC_REQUEST, &Message::default(), timeout).await?;
+        reply.params.into_iter()
+            .map(|(prefix, subscribers)| subscribers.parse().map(|subscribers| (prefix, subscribers)).map_err(|_| Error::ConversionError))
+            .collect()
+    }
+
+    /// Asks every module for its info and collects the answers received within `timeout`.
+    pub async fn discover_modules(&self, timeout: Duration) -> Result<Vec<ModuleInfo>, Error> {
+        let mut responses = self.receiver.observe(MODULE_INFO_TOPIC_RESPONSE).await?;
+        let mut modules = BTreeMap::new();
+        let result = self.send(MODULE_INFO_TOPIC_REQUEST, &Message::default()).await;
+        if result.is_ok() {
+            let deadline = tokio::time::Instant::now() + timeout;
+            while let Ok(Some(response)) = tokio::time::timeout_at(deadline, responses.recv()).await {
+                if let Some(info) = ModuleInfo::from_message(&response) {
+                    modules.insert(info.name.clone(), info);
+                }
+            }
+        }
+        drop(responses);
+        self.receiver.unobserve(MODULE_INFO_TOPIC_RESPONSE).await?;
+        result?;
+        Ok(modules.into_values().collect())
+    }
+
+    /// Discovers the modules offering `capability`, e.g. `"speech-to-text"`.
+    pub async fn discover_modules_with_capability(&self, capability: &str, timeout: Duration) -> Result<Vec<ModuleInfo>, Error> {
+        let mut modules = self.discover_modules(timeout).await?;
+        modules.retain(|module| module.has_capability(capability));
+        Ok(modules)
+    }
+}
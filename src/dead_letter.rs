@@ -0,0 +1,82 @@
+use std::collections::BTreeMap;
+use bytes::Bytes;
+use crate::codec;
+use crate::error::Error;
+use crate::message::{Message, MessageType};
+
+/// Topic of the messages that could not be decoded or handled.
+pub const DEAD_LETTER_TOPIC: &str = "deadletter";
+/// Param of a dead letter with the topic of the original message.
+pub const TOPIC_PARAM: &str = "topic";
+/// Param of a dead letter with the reason of the failure.
+pub const REASON_PARAM: &str = "reason";
+/// Param of a dead letter with the name of the module that gave up on the message.
+pub const MODULE_PARAM: &str = "module";
+
+/// A message that could not be handled, published on [`DEAD_LETTER_TOPIC`]
+/// with the original message encoded in the data, so it can be inspected and published again.
+/// # Examples
+/// ```rust
+/// use alfred_core::dead_letter::DeadLetter;
+/// use alfred_core::message::Message;
+///
+/// let dead_letter = DeadLetter {
+///     topic: "chat".to_string(),
+///     reason: "No handler found".to_string(),
+///     module: "routing".to_string(),
+///     message: Message { text: "hello".to_string(), ..Message::default() }
+/// };
+/// assert_eq!(DeadLetter::from_message(&dead_letter.to_message().unwrap()).unwrap(), dead_letter);
+/// assert!(DeadLetter::from_message(&Message::default()).is_err());
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DeadLetter {
+    pub topic: String,
+    pub reason: String,
+    pub module: String,
+    pub message: Message
+}
+
+impl DeadLetter {
+    /// Fails if the original message cannot be encoded (see [`Message::try_compress`]).
+    pub fn to_message(&self) -> Result<Message, Error> {
+        Ok(Message {
+            message_type: MessageType::Error,
+            text: format!("{} (topic {})", self.reason, self.topic),
+            sender: self.module.clone(),
+            params: BTreeMap::from([
+                (TOPIC_PARAM.to_string(), self.topic.clone()),
+                (REASON_PARAM.to_string(), self.reason.clone()),
+                (MODULE_PARAM.to_string(), self.module.clone())
+            ]),
+            data: self.message.try_compress()?.into(),
+            ..Message::default()
+        })
+    }
+
+    /// Parses a dead letter, failing if the original message cannot be decoded
+    /// (e.g. the dead letter of a malformed message).
+    pub fn from_message(message: &Message) -> Result<Self, Error> {
+        let param = |key: &str| message.params.get(key).cloned().ok_or(Error::ConversionError);
+        Ok(Self {
+            topic: param(TOPIC_PARAM)?,
+            reason: param(REASON_PARAM)?,
+            module: param(MODULE_PARAM).unwrap_or_default(),
+            message: codec::decode(&message.data)?
+        })
+    }
+}
+
+/// Dead letter of a message that could not be decoded: the raw frames following the topic are in the data.
+pub(crate) fn malformed(topic: &str, frames: &[Bytes], reason: &str) -> Message {
+    Message {
+        message_type: MessageType::Error,
+        text: format!("Malformed message on topic {topic}: {reason}"),
+        params: BTreeMap::from([
+            (TOPIC_PARAM.to_string(), topic.to_string()),
+            (REASON_PARAM.to_string(), reason.to_string())
+        ]),
+        data: frames.get(1..).unwrap_or_default().concat().into(),
+        ..Message::default()
+    }
+}
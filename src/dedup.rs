@@ -0,0 +1,27 @@
+use std::collections::{HashSet, VecDeque};
+
+/// Identifiers of the last messages received, to drop the copies of a message sent again.
+pub(crate) struct RecentIds {
+    capacity: usize,
+    ids: HashSet<String>,
+    order: VecDeque<String>
+}
+
+impl RecentIds {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self { capacity, ids: HashSet::with_capacity(capacity), order: VecDeque::with_capacity(capacity) }
+    }
+
+    /// Records `id`, returning `false` if it was already recorded. The oldest id is forgotten when full.
+    pub(crate) fn insert(&mut self, id: &str) -> bool {
+        if self.ids.contains(id) { return false; }
+        if self.order.len() == self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.ids.remove(&oldest);
+            }
+        }
+        self.ids.insert(id.to_string());
+        self.order.push_back(id.to_string());
+        true
+    }
+}
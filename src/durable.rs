@@ -0,0 +1,75 @@
+use std::collections::BTreeMap;
+use crate::error::Error;
+use crate::message::Message;
+
+/// Requests registering a [`DurableSubscription`] in the queue bin.
+pub const DURABLE_SUBSCRIBE_TOPIC: &str = "queue.subscribe";
+/// Requests removing a [`DurableSubscription`] from the queue bin (only module and topic are used).
+pub const DURABLE_UNSUBSCRIBE_TOPIC: &str = "queue.unsubscribe";
+/// Prefix of the topics on which the queue bin replays the stored messages, followed by the module name.
+pub const REPLAY_TOPIC_PREFIX: &str = "alfred.replay";
+/// Param of a replayed message holding the topic it was published on.
+pub const REPLAY_TOPIC_PARAM: &str = "alfred.replay.topic";
+const MODULE_PARAM: &str = "module";
+const TOPIC_PARAM: &str = "topic";
+const MAX_SIZE_PARAM: &str = "max_size";
+const MAX_AGE_PARAM: &str = "max_age";
+
+/// Topic on which the stored messages of `module` are replayed: only the module subscribes to it,
+/// so the other subscribers of the original topic neither receive nor acknowledge them.
+pub fn replay_topic(module: &str) -> String {
+    format!("{REPLAY_TOPIC_PREFIX}.{module}")
+}
+
+/// Subscription of `module` to `topic` (a prefix), kept by the queue bin while the module is offline.
+///
+/// The stored messages are published again on the [`replay_topic`] of the module when it is back.
+/// The limits apply to the whole queue of the module; the queue bin has defaults for the missing ones.
+/// # Examples
+/// ```rust
+/// use alfred_core::durable::DurableSubscription;
+///
+/// let subscription = DurableSubscription {
+///     module: "heater".to_string(),
+///     topic: "heater.".to_string(),
+///     max_size: Some(100),
+///     max_age: None
+/// };
+/// assert_eq!(DurableSubscription::from_message(&subscription.to_message()).unwrap(), subscription);
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DurableSubscription {
+    pub module: String,
+    pub topic: String,
+    /// Maximum number of messages kept, the oldest being dropped
+    pub max_size: Option<usize>,
+    /// Maximum age (ms) of the messages kept
+    pub max_age: Option<u64>
+}
+
+impl DurableSubscription {
+    pub fn to_message(&self) -> Message {
+        let mut params = BTreeMap::from([
+            (MODULE_PARAM.to_string(), self.module.clone()),
+            (TOPIC_PARAM.to_string(), self.topic.clone())
+        ]);
+        if let Some(max_size) = self.max_size {
+            params.insert(MAX_SIZE_PARAM.to_string(), max_size.to_string());
+        }
+        if let Some(max_age) = self.max_age {
+            params.insert(MAX_AGE_PARAM.to_string(), max_age.to_string());
+        }
+        Message { sender: self.module.clone(), params, ..Message::default() }
+    }
+
+    pub fn from_message(message: &Message) -> Result<Self, Error> {
+        let param = |key: &str| message.params.get(key).cloned().ok_or(Error::ConversionError);
+        let limit = |key: &str| message.params.get(key).map(|limit| limit.parse().map_err(|_| Error::ConversionError)).transpose();
+        Ok(Self {
+            module: param(MODULE_PARAM)?,
+            topic: param(TOPIC_PARAM)?,
+            max_size: limit(MAX_SIZE_PARAM)?.map(|max_size: u64| usize::try_from(max_size).unwrap_or(usize::MAX)),
+            max_age: limit(MAX_AGE_PARAM)?
+        })
+    }
+}
@@ -0,0 +1,152 @@
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::os::unix::fs::FileTypeExt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, UnixListener, UnixStream};
+use crate::error::Error;
+
+pub const TCP_SCHEME: &str = "tcp://";
+pub const IPC_SCHEME: &str = "ipc://";
+/// Host binding every interface, as in `tcp://*:1234`.
+pub const ANY_HOST: &str = "*";
+const ANY_ADDRESS: &str = "0.0.0.0";
+const ANY_ADDRESS_V6: &str = "::";
+const LOCALHOST: &str = "127.0.0.1";
+const LOCALHOST_V6: &str = "::1";
+
+/// Address the broker binds: `tcp://host:port` or `ipc:///path/to/socket`.
+/// IPv6 hosts are written between brackets, as in `tcp://[::1]:5678`, and kept without them.
+/// # Examples
+/// ```rust
+/// use std::path::PathBuf;
+/// use alfred_core::endpoint::Endpoint;
+///
+/// let tcp: Endpoint = "tcp://*:5678".parse().unwrap();
+/// assert_eq!(tcp, Endpoint::Tcp { host: "*".to_string(), port: 5678 });
+/// assert_eq!(tcp.connect_url(), "tcp://127.0.0.1:5678");
+/// let ipc: Endpoint = "ipc:///run/alfred/pub.sock".parse().unwrap();
+/// assert_eq!(ipc, Endpoint::Ipc(PathBuf::from("/run/alfred/pub.sock")));
+/// assert_eq!(ipc.to_string(), "ipc:///run/alfred/pub.sock");
+/// assert!("udp://127.0.0.1:5678".parse::<Endpoint>().is_err());
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Endpoint {
+    Tcp { host: String, port: u16 },
+    Ipc(PathBuf)
+}
+
+impl Endpoint {
+    /// URL the modules connect to in order to reach this endpoint.
+    pub fn connect_url(&self) -> String {
+        match self {
+            Self::Tcp { host, port } if host == ANY_HOST || host == ANY_ADDRESS => format!("{TCP_SCHEME}{LOCALHOST}:{port}"),
+            Self::Tcp { host, port } if host == ANY_ADDRESS_V6 => format!("{TCP_SCHEME}[{LOCALHOST_V6}]:{port}"),
+            Self::Tcp { .. } | Self::Ipc(_) => self.to_string()
+        }
+    }
+}
+
+impl FromStr for Endpoint {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(address) = s.strip_prefix(TCP_SCHEME) {
+            let (host, port) = address.rsplit_once(':').ok_or_else(|| Error::EndpointError(s.to_string()))?;
+            let port = port.parse().map_err(|_| Error::EndpointError(s.to_string()))?;
+            let host = match host.strip_prefix('[') {
+                Some(host) => host.strip_suffix(']').ok_or_else(|| Error::EndpointError(s.to_string()))?,
+                None => host
+            };
+            if host.is_empty() { return Err(Error::EndpointError(s.to_string())); }
+            return Ok(Self::Tcp { host: host.to_string(), port });
+        }
+        match s.strip_prefix(IPC_SCHEME) {
+            Some(path) if !path.is_empty() => Ok(Self::Ipc(PathBuf::from(path))),
+            Some(_) | None => Err(Error::EndpointError(s.to_string()))
+        }
+    }
+}
+
+impl Display for Endpoint {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Tcp { host, port } if host.contains(':') => write!(f, "{TCP_SCHEME}[{host}]:{port}"),
+            Self::Tcp { host, port } => write!(f, "{TCP_SCHEME}{host}:{port}"),
+            Self::Ipc(path) => write!(f, "{IPC_SCHEME}{}", path.display())
+        }
+    }
+}
+
+pub(crate) trait Stream: AsyncRead + AsyncWrite + Send + Unpin {}
+impl<T: AsyncRead + AsyncWrite + Send + Unpin> Stream for T {}
+
+/// Listener bound to an [`Endpoint`]. The socket file of an ipc endpoint is removed when dropped.
+pub(crate) enum Listener {
+    Tcp(TcpListener),
+    Ipc(UnixListener, PathBuf)
+}
+
+impl Listener {
+    pub(crate) async fn bind(endpoint: &Endpoint) -> Result<Self, Error> {
+        let bind_error = |error: std::io::Error| Error::BindError(endpoint.to_string(), error.to_string());
+        match endpoint {
+            Endpoint::Tcp { host, port } => {
+                let host = if host == ANY_HOST { ANY_ADDRESS } else { host.as_str() };
+                Ok(Self::Tcp(TcpListener::bind((host, *port)).await.map_err(bind_error)?))
+            },
+            Endpoint::Ipc(path) => {
+                remove_stale_socket(path).await.map_err(bind_error)?;
+                Ok(Self::Ipc(UnixListener::bind(path).map_err(bind_error)?, path.clone()))
+            }
+        }
+    }
+
+    /// Endpoint actually bound, e.g. with the port picked by the system for port 0.
+    pub(crate) fn endpoint(&self) -> Result<Endpoint, Error> {
+        match self {
+            Self::Tcp(listener) => {
+                let address = listener.local_addr()?;
+                Ok(Endpoint::Tcp { host: address.ip().to_string(), port: address.port() })
+            },
+            Self::Ipc(_, path) => Ok(Endpoint::Ipc(path.clone()))
+        }
+    }
+
+    /// Accepts a new connection, returning it with a description of the peer.
+    pub(crate) async fn accept(&self) -> Result<(Box<dyn Stream>, String), Error> {
+        match self {
+            Self::Tcp(listener) => {
+                let (stream, address) = listener.accept().await?;
+                stream.set_nodelay(true)?;
+                Ok((Box::new(stream), address.to_string()))
+            },
+            Self::Ipc(listener, path) => {
+                let (stream, _) = listener.accept().await?;
+                Ok((Box::new(stream), path.display().to_string()))
+            }
+        }
+    }
+}
+
+/// Removes a socket file left by a previous run, which would make the bind fail.
+/// A socket still accepting connections or any other kind of file is kept: the address is in use.
+async fn remove_stale_socket(path: &Path) -> io::Result<()> {
+    let Ok(metadata) = std::fs::symlink_metadata(path) else { return Ok(()) };
+    if !metadata.file_type().is_socket() {
+        return Err(io::Error::new(io::ErrorKind::AddrInUse, "the path exists and is not a socket"));
+    }
+    if UnixStream::connect(path).await.is_ok() {
+        return Err(io::Error::new(io::ErrorKind::AddrInUse, "the socket is used by another process"));
+    }
+    std::fs::remove_file(path)
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        if let Self::Ipc(_, path) = self {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
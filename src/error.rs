@@ -1,3 +1,4 @@
+use bytes::Bytes;
 use zeromq::ZmqError;
 
 #[derive(Debug)]
@@ -6,6 +7,8 @@ use zeromq::ZmqError;
 pub enum Error {
     #[error("Error on connection")]
     ConnectionError,
+    #[error("The broker did not answer the handshake in time")]
+    HandshakeTimeout,
     #[error("Error publishing {1} in topic {0}")]
     PublishError(String, String),
     #[error("Error subscribing to topic {0}")]
@@ -16,6 +19,13 @@ pub enum Error {
     ConversionError,
     #[error("No response topic found")]
     ReplyError,
+    #[error("No response received in time for the request sent to topic {0}")]
+    RequestTimeout(String),
+    /// A message that could not be decoded, with its raw frames (the topic first)
+    #[error("Malformed message on topic {topic}: {reason}")]
+    MalformedMessage { topic: String, frames: Vec<Bytes>, reason: String },
+    #[error("No acknowledgement received for the message sent to topic {0}")]
+    AckTimeout(String),
     #[error("MessageCompressionError: {0}")]
     MessageCompressionError(String),
     #[error("Missing env property: {0}")]
@@ -24,6 +34,14 @@ pub enum Error {
     MissingFilePropertyError(String),
     #[error("ZmqError: {0}")]
     ZmqError(ZmqError),
+    #[error("Invalid endpoint: {0}")]
+    EndpointError(String),
+    #[error("Unable to bind {0}: {1}")]
+    BindError(String, String),
+    #[error("ZMTP protocol error: {0}")]
+    ProtocolError(String),
+    #[error("IO error: {0}")]
+    IoError(std::io::Error),
 }
 
 impl From<MessageCompressionError> for Error {
@@ -32,6 +50,12 @@ impl From<MessageCompressionError> for Error {
     }
 }
 
+impl From<std::io::Error> for Error {
+    fn from(value: std::io::Error) -> Self {
+        Self::IoError(value)
+    }
+}
+
 impl From<ZmqError> for Error {
     fn from(value: ZmqError) -> Self {
         Self::ZmqError(value)
@@ -45,6 +69,24 @@ pub enum MessageCompressionError{
     FieldNotFound(String),
     #[error("message type {0} not found!")]
     MessageType(String),
-    #[error("error during decompression")]
-    DecompressionError()
+    #[error("empty frame")]
+    EmptyFrame,
+    #[error("unsupported wire format version {0}")]
+    UnsupportedVersion(u8),
+    #[error("invalid {0} count")]
+    BadCount(String),
+    #[error("field {0} is truncated")]
+    TruncatedField(String),
+    #[error("field {0} is not valid UTF-8")]
+    InvalidUtf8(String),
+    #[error("expected {expected} params, found {found}")]
+    TruncatedParams { expected: usize, found: usize },
+    #[error("expected {expected} response topics, found {found}")]
+    TruncatedResponseTopics { expected: usize, found: usize },
+    #[error("codec {0} is not supported")]
+    UnsupportedCodec(String),
+    #[error("codec error: {0}")]
+    CodecError(String),
+    #[error("custom message type code {0:#04x} is reserved")]
+    InvalidCustomCode(u8),
 }
\ No newline at end of file
@@ -0,0 +1,43 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use crate::error::Error;
+use crate::message::Message;
+
+/// Suffix turning a topic pattern into a prefix match.
+pub const TOPIC_WILDCARD: char = '*';
+
+/// Result of a handler: `Some` contains the reply, published using [`Message::reply_with`].
+pub type HandlerResult = Result<Option<Message>, Error>;
+pub type HandlerFuture = Pin<Box<dyn Future<Output = HandlerResult> + Send>>;
+pub type Handler = Arc<dyn Fn(String, Message) -> HandlerFuture + Send + Sync>;
+
+pub fn handler<F, Fut>(handler: F) -> Handler
+where
+    F: Fn(String, Message) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = HandlerResult> + Send + 'static,
+{
+    Arc::new(move |topic, message| Box::pin(handler(topic, message)))
+}
+
+/// Returns the topic to subscribe to in order to receive the messages matching the pattern.
+pub fn subscription(pattern: &str) -> &str {
+    pattern.strip_suffix(TOPIC_WILDCARD).unwrap_or(pattern)
+}
+
+/// Checks if a topic matches a pattern: patterns ending with [`TOPIC_WILDCARD`] match every topic
+/// starting with the rest of the pattern, the others only match the same topic.
+/// # Examples
+/// ```rust
+/// use alfred_core::handler::topic_matches;
+///
+/// assert!(topic_matches("chat", "chat"));
+/// assert!(!topic_matches("chat", "chat.response"));
+/// assert!(topic_matches("event.telegram.*", "event.telegram.new_incoming_message"));
+/// assert!(!topic_matches("event.telegram.*", "event.openai.response"));
+/// assert!(topic_matches("*", "any.topic"));
+/// ```
+pub fn topic_matches(pattern: &str, topic: &str) -> bool {
+    pattern.strip_suffix(TOPIC_WILDCARD)
+        .map_or(pattern == topic, |prefix| topic.starts_with(prefix))
+}
@@ -2,14 +2,31 @@
 pub use tokio;
 pub use log;
 pub use clap;
+pub use bytes;
 
 pub mod message;
+pub mod codec;
 pub mod config;
 
 pub mod error;
 mod module;
+pub mod module_info;
+pub mod handler;
 pub mod connection;
+pub mod transport;
+pub mod memory;
+pub mod broker;
+pub mod endpoint;
+pub mod dead_letter;
+pub mod durable;
+pub mod queue;
+pub mod recorder;
+pub mod registry;
+mod zmtp;
 mod zmq_connection;
+mod receiver;
+mod dedup;
+mod supervisor;
 
 pub use module::AlfredModule;
 pub use module::ModuleDetailsBuilder;
@@ -0,0 +1,186 @@
+use std::collections::BTreeSet;
+use std::sync::Arc;
+use log::warn;
+use bytes::Bytes;
+use tokio::sync::{broadcast, mpsc, Mutex};
+use crate::codec;
+use crate::connection::is_probe_topic;
+use crate::error::Error;
+use crate::message::Message;
+use crate::transport::{Frames, Publisher, RawPublisher, RawSubscriber, Subscriber, Transport, TransportFuture};
+
+const BUS_CAPACITY: usize = 1024;
+
+type Envelope = (String, Message);
+
+/// In-process transport, to test modules without a running daemon.
+///
+/// Every message published on the bus is delivered to the subscribers listening to a prefix
+/// of its topic, like with the daemon. Messages published by the modules are also
+/// recorded, so tests can assert on them; probes used by the connection are not recorded.
+/// Raw subscribers get the messages encoded with the default codec, and raw publishers
+/// only publish the frames which can be decoded.
+/// # Examples
+/// ```rust
+/// use std::sync::Arc;
+/// use alfred_core::{AlfredModule, ModuleDetailsBuilder};
+/// use alfred_core::memory::MemoryBus;
+/// use alfred_core::message::Message;
+///
+/// # tokio::runtime::Runtime::new().unwrap().block_on(async {
+/// let bus = MemoryBus::new();
+/// let details = ModuleDetailsBuilder::new()
+///     .module_name("echo")
+///     .version("1.0.0")
+///     .transport(Arc::new(bus.clone()))
+///     .build();
+/// let mut module = AlfredModule::new_with_details(details).await.unwrap();
+/// module.on("echo", |_, message: Message| async move { Ok(Some(message)) }).await.unwrap();
+/// tokio::spawn(async move { module.run().await });
+///
+/// let mut request = Message { text: "hello".to_string(), ..Message::default() };
+/// request.response_topics.push_back("echo.response".to_string());
+/// bus.inject("echo", &request);
+///
+/// let (topic, reply) = loop {
+///     let (topic, message) = bus.next_published().await.unwrap();
+///     if topic == "echo.response" { break (topic, message); }
+/// };
+/// assert_eq!(topic, "echo.response");
+/// assert_eq!(reply.text, "hello");
+/// # });
+/// ```
+#[derive(Clone)]
+pub struct MemoryBus {
+    sender: broadcast::Sender<Envelope>,
+    published_sender: mpsc::UnboundedSender<Envelope>,
+    published: Arc<Mutex<mpsc::UnboundedReceiver<Envelope>>>
+}
+
+impl MemoryBus {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(BUS_CAPACITY);
+        let (published_sender, published) = mpsc::unbounded_channel();
+        Self { sender, published_sender, published: Arc::new(Mutex::new(published)) }
+    }
+
+    /// Delivers a message to the subscribers, as if it was published by another module.
+    pub fn inject(&self, topic: &str, message: &Message) {
+        let _ = self.sender.send((topic.to_string(), message.clone()));
+    }
+
+    /// Waits for the next message published by a module connected to the bus.
+    pub async fn next_published(&self) -> Option<(String, Message)> {
+        self.published.lock().await.recv().await
+    }
+}
+
+impl Default for MemoryBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Transport for MemoryBus {
+    fn connect(&self) -> TransportFuture<'_, (Box<dyn Subscriber>, Box<dyn Publisher>)> {
+        Box::pin(async move {
+            let subscriber: Box<dyn Subscriber> = Box::new(MemorySubscriber {
+                receiver: self.sender.subscribe(),
+                topics: BTreeSet::new()
+            });
+            let publisher: Box<dyn Publisher> = Box::new(MemoryPublisher { bus: self.clone() });
+            Ok((subscriber, publisher))
+        })
+    }
+
+    fn connect_raw(&self) -> TransportFuture<'_, (Box<dyn RawSubscriber>, Box<dyn RawPublisher>)> {
+        Box::pin(async move {
+            let subscriber: Box<dyn RawSubscriber> = Box::new(MemorySubscriber {
+                receiver: self.sender.subscribe(),
+                topics: BTreeSet::new()
+            });
+            let publisher: Box<dyn RawPublisher> = Box::new(MemoryPublisher { bus: self.clone() });
+            Ok((subscriber, publisher))
+        })
+    }
+}
+
+struct MemorySubscriber {
+    receiver: broadcast::Receiver<Envelope>,
+    topics: BTreeSet<String>
+}
+
+impl Subscriber for MemorySubscriber {
+    fn listen<'a>(&'a mut self, topic: &'a str) -> TransportFuture<'a, ()> {
+        self.topics.insert(topic.to_string());
+        Box::pin(async { Ok(()) })
+    }
+
+    fn unlisten<'a>(&'a mut self, topic: &'a str) -> TransportFuture<'a, ()> {
+        self.topics.remove(topic);
+        Box::pin(async { Ok(()) })
+    }
+
+    fn receive(&mut self) -> TransportFuture<'_, (String, Message)> {
+        Box::pin(async move {
+            loop {
+                match self.receiver.recv().await {
+                    Ok((topic, message)) => {
+                        if self.topics.iter().any(|subscription| topic.starts_with(subscription.as_str())) {
+                            return Ok((topic, message));
+                        }
+                    },
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => warn!("{skipped} messages skipped on the memory bus"),
+                    Err(broadcast::error::RecvError::Closed) => return Err(Error::GetMessageError)
+                }
+            }
+        })
+    }
+}
+
+struct MemoryPublisher {
+    bus: MemoryBus
+}
+
+impl Publisher for MemoryPublisher {
+    fn send<'a>(&'a mut self, topic: &'a str, message: &'a Message) -> TransportFuture<'a, ()> {
+        if !is_probe_topic(topic.as_bytes()) {
+            let _ = self.bus.published_sender.send((topic.to_string(), message.clone()));
+        }
+        self.bus.inject(topic, message);
+        Box::pin(async { Ok(()) })
+    }
+}
+
+impl RawSubscriber for MemorySubscriber {
+    fn listen<'a>(&'a mut self, topic: &'a str) -> TransportFuture<'a, ()> {
+        Subscriber::listen(self, topic)
+    }
+
+    fn unlisten<'a>(&'a mut self, topic: &'a str) -> TransportFuture<'a, ()> {
+        Subscriber::unlisten(self, topic)
+    }
+
+    fn receive(&mut self) -> TransportFuture<'_, Frames> {
+        Box::pin(async move {
+            loop {
+                let (topic, message) = Subscriber::receive(self).await?;
+                match message.try_compress() {
+                    Ok(frame) => return Ok(vec![Bytes::from(topic), Bytes::from(frame)]),
+                    Err(e) => warn!("Message on topic {topic} cannot be encoded: {e}")
+                }
+            }
+        })
+    }
+}
+
+impl RawPublisher for MemoryPublisher {
+    fn send(&mut self, frames: Frames) -> TransportFuture<'_, ()> {
+        let topic = frames.first().map(|topic| String::from_utf8(topic.to_vec()));
+        if let (Some(Ok(topic)), Some(Ok(message))) = (topic, frames.get(1).map(|frame| codec::decode(frame))) {
+            return Box::pin(async move { Publisher::send(self, &topic, &message).await });
+        }
+        warn!("Frames which cannot be decoded are not published on the memory bus");
+        Box::pin(async { Ok(()) })
+    }
+}
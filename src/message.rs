@@ -1,42 +1,128 @@
 use std::{fmt, str::FromStr};
+use std::ops::RangeInclusive;
 use std::collections::{BTreeMap, LinkedList};
-use itertools::Itertools;
-use serde_derive::Deserialize;
+use std::time::{SystemTime, UNIX_EPOCH};
+use bytes::Bytes;
+use serde_derive::{Deserialize, Serialize};
 use crate::error::MessageCompressionError;
 
 const MESSAGE_SEPARATOR : char = 0x0 as char;
+/// Version of the wire format written by [`Message::compress`].
+pub const WIRE_VERSION: u8 = 2;
+/// v1 frames always start with a UTF-8 lead byte, so versioned frames start with a
+/// UTF-8 continuation byte (`0b10xx_xxxx`) carrying the version number.
+const VERSION_FLAG: u8 = 0x80;
+const VERSION_MASK: u8 = 0xC0;
 
-#[derive(Debug, PartialEq, Eq, Clone, Deserialize, Default)]
+/// Codes available to third-party message types ([`MessageType::Custom`]).
+/// Codes below this range are reserved for the core.
+pub const CUSTOM_CODES: RangeInclusive<u8> = 0x80..=0xFD;
+const NAMED_CODE: u8 = 0xFE;
+
+/// Kind of content carried by a [`Message`].
+///
+/// Modules can define their own kinds, without a core release, using either a code in
+/// [`CUSTOM_CODES`] or a name. Unknown codes are decoded as [`MessageType::Custom`],
+/// so peers never fail on kinds introduced after their release.
+/// # Examples
+/// ```rust
+/// use std::str::FromStr;
+/// use alfred_core::message::{Message, MessageType};
+///
+/// for message_type in [MessageType::Video, MessageType::Custom(0x80), MessageType::Named(String::from("weather"))] {
+///     let message = Message { message_type: message_type.clone(), ..Message::default() };
+///     assert_eq!(Message::decompress(&message.compress()).unwrap().message_type, message_type);
+///     assert_eq!(MessageType::from_str(message_type.to_string().as_str()), Ok(message_type));
+/// }
+/// assert_eq!(MessageType::from_code(0x42), MessageType::Custom(0x42));
+/// ```
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize, Default)]
 pub enum MessageType {
     #[default]
     Unknown,
     Text,
     Audio,
     Photo,
-    ModuleInfo
+    Video,
+    Document,
+    Location,
+    Command,
+    Event,
+    Json,
+    Error,
+    ModuleInfo,
+    Custom(u8),
+    Named(String)
 }
 
 impl MessageType {
-    pub const fn compress(&self) -> char {
+    pub const fn code(&self) -> u8 {
         match self {
-            Self::Unknown => 0x00 as char,
-            Self::Text => 0x01 as char,
-            Self::Audio => 0x2 as char,
-            Self::Photo => 0x03 as char,
-            Self::ModuleInfo => 0xFF as char,
+            Self::Unknown => 0x00,
+            Self::Text => 0x01,
+            Self::Audio => 0x02,
+            Self::Photo => 0x03,
+            Self::Video => 0x04,
+            Self::Document => 0x05,
+            Self::Location => 0x06,
+            Self::Command => 0x07,
+            Self::Event => 0x08,
+            Self::Json => 0x09,
+            Self::Error => 0x0A,
+            Self::ModuleInfo => 0xFF,
+            Self::Custom(code) => *code,
+            Self::Named(_) => NAMED_CODE,
         }
     }
 
-    pub fn decompress(val: char) -> Result<Self, String> {
-        let u8_val = val as u8;
-        Ok(match u8_val {
+    /// Returns the type matching the code. Named types carry their name next to the code,
+    /// so they cannot be built from the code alone.
+    pub const fn from_code(code: u8) -> Self {
+        match code {
             0x00 => Self::Unknown,
             0x01 => Self::Text,
             0x02 => Self::Audio,
             0x03 => Self::Photo,
+            0x04 => Self::Video,
+            0x05 => Self::Document,
+            0x06 => Self::Location,
+            0x07 => Self::Command,
+            0x08 => Self::Event,
+            0x09 => Self::Json,
+            0x0A => Self::Error,
             0xFF => Self::ModuleInfo,
-            _ => Err(format!("{val} is not a valid MessageType."))?
-        })
+            code => Self::Custom(code)
+        }
+    }
+
+    /// Builds a [`MessageType::Custom`] type, checking that its code is in [`CUSTOM_CODES`].
+    pub const fn custom(code: u8) -> Result<Self, MessageCompressionError> {
+        if *CUSTOM_CODES.start() <= code && code <= *CUSTOM_CODES.end() {
+            Ok(Self::Custom(code))
+        } else {
+            Err(MessageCompressionError::InvalidCustomCode(code))
+        }
+    }
+
+    /// Fails on custom codes taken by the core types, which every codec rejects. Other codes outside
+    /// [`CUSTOM_CODES`] are accepted, as they are decoded from peers knowing more core types and may be relayed.
+    pub fn check_encodable(&self) -> Result<(), MessageCompressionError> {
+        if let Self::Custom(code) = self {
+            if *code == NAMED_CODE || !matches!(Self::from_code(*code), Self::Custom(_)) {
+                return Err(MessageCompressionError::InvalidCustomCode(*code));
+            }
+        }
+        Ok(())
+    }
+
+    pub const fn compress(&self) -> char {
+        self.code() as char
+    }
+
+    pub fn decompress(val: char) -> Result<Self, String> {
+        u8::try_from(val)
+            .map(Self::from_code)
+            .map_err(|_| format!("{val} is not a valid MessageType."))
     }
 }
 
@@ -49,31 +135,68 @@ impl FromStr for MessageType {
             "Text" => Self::Text,
             "Audio" => Self::Audio,
             "Photo" => Self::Photo,
+            "Video" => Self::Video,
+            "Document" => Self::Document,
+            "Location" => Self::Location,
+            "Command" => Self::Command,
+            "Event" => Self::Event,
+            "Json" => Self::Json,
+            "Error" => Self::Error,
             "ModuleInfo" => Self::ModuleInfo,
-            _ => Err(format!("{s} is not a valid MessageType."))?
+            _ => {
+                let custom_code = s.strip_prefix("Custom(")
+                    .and_then(|code| code.strip_suffix(')'))
+                    .and_then(|code| code.parse::<u8>().ok());
+                let name = s.strip_prefix("Named(").and_then(|name| name.strip_suffix(')'));
+                match (custom_code, name) {
+                    (Some(code), _) => Self::custom(code).map_err(|e| e.to_string())?,
+                    (None, Some(name)) => Self::Named(name.to_string()),
+                    (None, None) => Err(format!("{s} is not a valid MessageType."))?
+                }
+            }
         })
     }
 }
 
 impl fmt::Display for MessageType {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", match self {
+        let name = match self {
             Self::Unknown => "Unknown",
             Self::Text => "Text",
             Self::Audio => "Audio",
             Self::Photo => "Photo",
-            Self::ModuleInfo => "ModuleInfo"
-        })
+            Self::Video => "Video",
+            Self::Document => "Document",
+            Self::Location => "Location",
+            Self::Command => "Command",
+            Self::Event => "Event",
+            Self::Json => "Json",
+            Self::Error => "Error",
+            Self::ModuleInfo => "ModuleInfo",
+            Self::Custom(code) => return write!(f, "Custom({code})"),
+            Self::Named(name) => return write!(f, "Named({name})")
+        };
+        write!(f, "{name}")
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Default)]
+#[derive(Debug, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Message {
     pub message_type: MessageType,
     pub params: BTreeMap<String, String>,
     pub response_topics: LinkedList<String>,
     pub sender: String,
     pub text: String,
+    pub data: Bytes,
+    /// Unique identifier, assigned when the message is sent (if empty).
+    pub id: String,
+    /// Identifier of the first message of the conversation, propagated by [`Message::reply`].
+    pub correlation_id: String,
+    /// Creation time in milliseconds since the UNIX epoch, assigned when the message is sent (if 0).
+    pub timestamp: u64,
+    /// Number of times the message has been replied to or forwarded.
+    pub hops: u32,
 }
 
 impl Clone for Message {
@@ -84,6 +207,11 @@ impl Clone for Message {
             response_topics: self.response_topics.clone(),
             sender: self.sender.clone(),
             params: self.params.clone(),
+            data: self.data.clone(),
+            id: self.id.clone(),
+            correlation_id: self.correlation_id.clone(),
+            timestamp: self.timestamp,
+            hops: self.hops,
         }
     }
 }
@@ -103,11 +231,27 @@ impl Clone for Message {
 ///     response_topics: LinkedList::from([String::from("module.response"), String::from("other.module")]),
 ///     sender: String::from("0123"),
 ///     message_type: MessageType::Text,
-///     params
+///     params,
+///     ..Message::default()
 /// };
 /// let compress = message.compress();
-/// let result = Message::decompress(compress.as_str()).unwrap();
+/// let result = Message::decompress(compress.as_slice()).unwrap();
+/// assert_eq!(message, result);
+/// ```
+///
+/// Binary payloads (e.g. audio or photos) travel in `data` without any extra encoding:
+/// ```rust
+/// use alfred_core::bytes::Bytes;
+/// use alfred_core::message::{Message, MessageType};
+///
+/// let message = Message {
+///     message_type: MessageType::Audio,
+///     data: Bytes::from_static(&[0x00, 0xFF, 0x00, 0xC3, 0x28]),
+///     ..Message::default()
+/// };
+/// let result = Message::decompress(message.compress().as_slice()).unwrap();
 /// assert_eq!(message, result);
+/// assert_eq!(result.data.as_ref(), &[0x00, 0xFF, 0x00, 0xC3, 0x28]);
 /// ```
 impl Message {
 
@@ -115,66 +259,166 @@ impl Message {
         Self::default()
     }
 
+    pub fn new_id() -> String {
+        uuid::Uuid::new_v4().to_string()
+    }
+
+    pub fn now() -> u64 {
+        SystemTime::now().duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| u64::try_from(elapsed.as_millis()).unwrap_or(u64::MAX))
+    }
+
+    /// Assigns an id and a timestamp, if missing.
+    /// # Examples
+    /// ```rust
+    /// use alfred_core::message::Message;
+    ///
+    /// let mut message = Message::default();
+    /// message.stamp();
+    /// assert!(!message.id.is_empty());
+    /// assert!(message.timestamp > 0);
+    /// let stamped = message.clone();
+    /// message.stamp();
+    /// assert_eq!(message, stamped);
+    /// ```
+    pub fn stamp(&mut self) {
+        if self.id.is_empty() {
+            self.id = Self::new_id();
+        }
+        if self.timestamp == 0 {
+            self.timestamp = Self::now();
+        }
+    }
+
+    pub const fn is_stamped(&self) -> bool {
+        !self.id.is_empty() && self.timestamp != 0
+    }
+
+    /// Returns a copy of the message to be published again (e.g. by the routing),
+    /// keeping its identifiers and counting the new hop.
+    #[must_use]
+    pub fn forward(&self) -> Self {
+        Self {
+            hops: self.hops.saturating_add(1),
+            ..self.clone()
+        }
+    }
+
     /// compress
+    ///
+    /// Frame layout (v2): a version byte, the message type code, then every field
+    /// prefixed by its length (or item count) as an unsigned LEB128 number.
+    /// Timestamp and hops are written as LEB128 numbers too.
+    /// Custom types with a code taken by the core are written as [`MessageType::Unknown`]:
+    /// use [`Message::try_compress`], which rejects them, to encode messages not built by the core.
     /// # Examples
     /// ```rust
-    /// use std::collections::{BTreeMap, LinkedList, VecDeque};
-    /// use std::io::Lines;
+    /// use std::collections::{BTreeMap, LinkedList};
     /// use alfred_core::message::{Message, MessageType};
     ///
-    /// const MESSAGE_SEPARATOR : char = 0x0 as char;
-    ///
     /// let message: Message = Message {
     ///     message_type: MessageType::Text,
     ///     text: String::from("data"),
-    ///     response_topics: LinkedList::from([String::from("module.response"), String::from("other.module")]),
+    ///     response_topics: LinkedList::from([String::from("module.response")]),
     ///     sender: String::from("0123"),
     ///     params: BTreeMap::from([
-    ///         (String::from("par1"), String::from("val1")),
-    ///         (String::from("par2"), String::from("val2"))
-    ///     ])
+    ///         (String::from("par1"), String::from("val1"))
+    ///     ]),
+    ///     ..Message::default()
     /// };
     /// let compressed = message.compress();
-    /// let expected_message_type = 0x1 as char;
-    /// let expected_num_params = 0x2 as char;
-    /// let expected_num_responses = 0x2 as char;
-    /// let expected = format!("{expected_message_type}{expected_num_params}{expected_num_responses}par1{MESSAGE_SEPARATOR}val1{MESSAGE_SEPARATOR}par2{MESSAGE_SEPARATOR}val2{MESSAGE_SEPARATOR}module.response{MESSAGE_SEPARATOR}other.module{MESSAGE_SEPARATOR}0123{MESSAGE_SEPARATOR}data");
+    /// let expected = [
+    ///     &[0x82, 0x01][..],
+    ///     &[0x00], &[0x00], &[0x00], &[0x00],
+    ///     &[0x01, 0x04], b"par1", &[0x04], b"val1",
+    ///     &[0x01, 0x0F], b"module.response",
+    ///     &[0x04], b"0123",
+    ///     &[0x04], b"data",
+    ///     &[0x00],
+    /// ].concat();
     /// assert_eq!(compressed, expected);
     /// ```
-    pub fn compress(&self) -> String {
+    pub fn compress(&self) -> Vec<u8> {
+        let code = if self.message_type.check_encodable().is_ok() { self.message_type.code() } else { MessageType::Unknown.code() };
+        let mut compressed = vec![VERSION_FLAG | WIRE_VERSION, code];
+        if let MessageType::Named(name) = &self.message_type {
+            Self::compress_field(&mut compressed, name.as_bytes());
+        }
+        Self::compress_field(&mut compressed, self.id.as_bytes());
+        Self::compress_field(&mut compressed, self.correlation_id.as_bytes());
+        Self::compress_number(&mut compressed, self.timestamp);
+        Self::compress_number(&mut compressed, u64::from(self.hops));
+        Self::compress_number(&mut compressed, self.params.len() as u64);
+        for (key, value) in &self.params {
+            Self::compress_field(&mut compressed, key.as_bytes());
+            Self::compress_field(&mut compressed, value.as_bytes());
+        }
+        Self::compress_number(&mut compressed, self.response_topics.len() as u64);
+        for response_topic in &self.response_topics {
+            Self::compress_field(&mut compressed, response_topic.as_bytes());
+        }
+        Self::compress_field(&mut compressed, self.sender.as_bytes());
+        Self::compress_field(&mut compressed, self.text.as_bytes());
+        Self::compress_field(&mut compressed, &self.data);
+        compressed
+    }
 
-        #[allow(clippy::cast_possible_truncation)]
-        let compress_number = |val: usize| {
-            char::from(val as u8).to_string()
+    /// Like [`Message::compress`], failing on custom types with a code taken by the core.
+    pub fn try_compress(&self) -> Result<Vec<u8>, MessageCompressionError> {
+        self.message_type.check_encodable()?;
+        Ok(self.compress())
+    }
+
+    fn compress_number(compressed: &mut Vec<u8>, mut number: u64) {
+        loop {
+            #[allow(clippy::cast_possible_truncation)]
+            let byte = (number & 0x7F) as u8;
+            number >>= 7;
+            if number == 0 {
+                compressed.push(byte);
+                return;
+            }
+            compressed.push(byte | 0x80);
+        }
+    }
+
+    fn compress_field(compressed: &mut Vec<u8>, field: &[u8]) {
+        Self::compress_number(compressed, field.len() as u64);
+        compressed.extend_from_slice(field);
+    }
+
+    fn decompress_char<'a>(comp: &'a [u8], field: &str) -> Result<(char, &'a [u8]), MessageCompressionError> {
+        let char_len = match comp.first().ok_or_else(|| MessageCompressionError::FieldNotFound(field.to_string()))? {
+            0xC0..=0xDF => 2,
+            0xE0..=0xEF => 3,
+            0xF0..=0xF7 => 4,
+            _ => 1
         };
+        let (char_bytes, rest) = comp.split_at_checked(char_len)
+            .ok_or_else(|| MessageCompressionError::TruncatedField(field.to_string()))?;
+        let value = std::str::from_utf8(char_bytes).ok()
+            .and_then(|char_str| char_str.chars().next())
+            .ok_or_else(|| MessageCompressionError::InvalidUtf8(field.to_string()))?;
+        Ok((value, rest))
+    }
 
-        let params = self.params.iter()
-            .map(|(k, v)| format!("{k}{MESSAGE_SEPARATOR}{v}{MESSAGE_SEPARATOR}"))
-            .join("");
+    fn decompress_count<'a>(comp: &'a [u8], field: &str) -> Result<(usize, &'a [u8]), MessageCompressionError> {
+        let (count, rest) = Self::decompress_char(comp, field)?;
+        let count = u8::try_from(count).map_err(|_| MessageCompressionError::BadCount(field.to_string()))?;
+        Ok((usize::from(count), rest))
+    }
 
-        [
-            self.message_type.compress().to_string(),
-            compress_number(self.params.len()),
-            compress_number(self.response_topics.len()),
-            params,
-            Itertools::intersperse(
-                self.response_topics.iter()
-                    .cloned(), MESSAGE_SEPARATOR.to_string()
-            ).collect(),
-            MESSAGE_SEPARATOR.to_string(),
-            self.sender.clone(),
-            MESSAGE_SEPARATOR.to_string(),
-            self.text.clone(),
-        ]
-            .into_iter()
-            .collect::<String>()
+    fn decompress_string(field: &[u8], field_name: &str) -> Result<String, MessageCompressionError> {
+        String::from_utf8(field.to_vec()).map_err(|_| MessageCompressionError::InvalidUtf8(field_name.to_string()))
     }
 
     /// decompress
+    ///
+    /// Both the current frame format and the legacy NUL-separated (v1) one are accepted,
+    /// so modules built on older releases can still be understood.
     /// # Examples
     /// ```rust
-    /// use std::collections::{BTreeMap, LinkedList, VecDeque};
-    /// use std::io::Lines;
+    /// use std::collections::{BTreeMap, LinkedList};
     /// use alfred_core::message::{Message, MessageType};
     ///
     /// const MESSAGE_SEPARATOR : char = 0x0 as char;
@@ -182,7 +426,7 @@ impl Message {
     /// let compressed_message_type = 0x01 as char;
     /// let compressed_num_params = 0x02 as char;
     /// let compressed_num_responses = 0x02 as char;
-    /// let decompressed = Message::decompress(format!("{compressed_message_type}{compressed_num_params}{compressed_num_responses}par1{MESSAGE_SEPARATOR}val1{MESSAGE_SEPARATOR}par2{MESSAGE_SEPARATOR}val2{MESSAGE_SEPARATOR}module.response{MESSAGE_SEPARATOR}other.module{MESSAGE_SEPARATOR}0123{MESSAGE_SEPARATOR}data").as_str());
+    /// let decompressed = Message::decompress(format!("{compressed_message_type}{compressed_num_params}{compressed_num_responses}par1{MESSAGE_SEPARATOR}val1{MESSAGE_SEPARATOR}par2{MESSAGE_SEPARATOR}val2{MESSAGE_SEPARATOR}module.response{MESSAGE_SEPARATOR}other.module{MESSAGE_SEPARATOR}0123{MESSAGE_SEPARATOR}data").as_bytes());
     /// assert!(decompressed.is_ok());
     /// let message: Message = Message {
     ///     message_type: MessageType::Text,
@@ -192,67 +436,220 @@ impl Message {
     ///     params: BTreeMap::from([
     ///         (String::from("par1"), String::from("val1")),
     ///         (String::from("par2"), String::from("val2"))
-    ///     ])
+    ///     ]),
+    ///     ..Message::default()
     /// };
     /// assert_eq!(message, decompressed.unwrap());
+    ///
+    /// // any content survives a round trip
+    /// let message = Message {
+    ///     text: String::from("multi\0line\0text"),
+    ///     params: (0..300).map(|i| (format!("par{i}"), format!("val\0{i}"))).collect(),
+    ///     ..Message::default()
+    /// };
+    /// assert_eq!(message, Message::decompress(message.compress().as_slice()).unwrap());
     /// ```
-    pub fn decompress(comp_str: &str) -> Result<Self, MessageCompressionError> {
-        let mut binding = comp_str.chars();
-        let mut get_next_char = || {
-            binding.nth(0).ok_or(MessageCompressionError::DecompressionError())
+    pub fn decompress(comp: &[u8]) -> Result<Self, MessageCompressionError> {
+        match comp.split_first() {
+            None => Err(MessageCompressionError::EmptyFrame),
+            Some((&version, frame)) if version & VERSION_MASK == VERSION_FLAG => {
+                match version & !VERSION_MASK {
+                    WIRE_VERSION => Self::decompress_versioned(frame),
+                    version => Err(MessageCompressionError::UnsupportedVersion(version))
+                }
+            },
+            Some(_) => Self::decompress_v1(comp)
+        }
+    }
+
+    fn decompress_versioned(comp: &[u8]) -> Result<Self, MessageCompressionError> {
+        let mut reader = FrameReader { frame: comp };
+        let message_type = match reader.read_byte("message_type")? {
+            NAMED_CODE => MessageType::Named(reader.read_string("message_type")?),
+            code => MessageType::from_code(code)
         };
+        let id = reader.read_string("id")?;
+        let correlation_id = reader.read_string("correlation_id")?;
+        let timestamp = reader.read_number("timestamp")?;
+        let hops = u32::try_from(reader.read_number("hops")?)
+            .map_err(|_| MessageCompressionError::BadCount(String::from("hops")))?;
 
-        let message_type_str = get_next_char()?;
-        let message_type = MessageType::decompress(message_type_str)
-            .map_err(|_| MessageCompressionError::DecompressionError())?;
+        let params_size = reader.read_count("params")?;
+        let mut params: BTreeMap<String, String> = BTreeMap::new();
+        for index in 0..params_size {
+            if reader.is_empty() {
+                return Err(MessageCompressionError::TruncatedParams { expected: params_size, found: index });
+            }
+            let key = reader.read_string("param key")?;
+            params.insert(key, reader.read_string("param value")?);
+        }
 
-        let mut offset = 0;
-        let params_size = (get_next_char()? as u8) as usize;
-        let response_topics_size = (get_next_char()? as u8) as usize;
+        let response_topics_size = reader.read_count("response_topics")?;
+        let mut response_topics = LinkedList::new();
+        for index in 0..response_topics_size {
+            if reader.is_empty() {
+                return Err(MessageCompressionError::TruncatedResponseTopics { expected: response_topics_size, found: index });
+            }
+            response_topics.push_back(reader.read_string("response topic")?);
+        }
 
-        let ser_msg = comp_str[3..].split(MESSAGE_SEPARATOR).collect::<Vec<&str>>();
+        Ok(Self {
+            message_type,
+            params,
+            response_topics,
+            sender: reader.read_string("sender")?,
+            text: reader.read_string("text")?,
+            data: Bytes::copy_from_slice(reader.read_field("data")?),
+            id,
+            correlation_id,
+            timestamp,
+            hops,
+        })
+    }
+
+    fn decompress_v1(comp: &[u8]) -> Result<Self, MessageCompressionError> {
+        let (message_type_char, comp) = Self::decompress_char(comp, "message_type")?;
+        let message_type = MessageType::decompress(message_type_char)
+            .map_err(|_| MessageCompressionError::MessageType(format!("{:#04x}", u32::from(message_type_char))))?;
+        let (params_size, comp) = Self::decompress_count(comp, "params")?;
+        let (response_topics_size, comp) = Self::decompress_count(comp, "response_topics")?;
+
+        // an empty list of response topics still leaves its (empty) field before the sender
+        let header_fields = 2 * params_size + response_topics_size.max(1) + 1;
+        let mut fields = comp.splitn(header_fields + 1, |byte| *byte == MESSAGE_SEPARATOR as u8);
 
         let mut params: BTreeMap<String, String> = BTreeMap::new();
         for index in 0..params_size {
-            params.insert(ser_msg[2*index].to_string(), ser_msg[2*index+1].to_string());
+            let truncated = || MessageCompressionError::TruncatedParams { expected: params_size, found: index };
+            let key = Self::decompress_string(fields.next().ok_or_else(truncated)?, "param key")?;
+            params.insert(key, Self::decompress_string(fields.next().ok_or_else(truncated)?, "param value")?);
         }
-        offset += 2*params_size;
 
-        let mut response_topics= LinkedList::new();
+        let mut response_topics = LinkedList::new();
         for index in 0..response_topics_size {
-            response_topics.push_back(ser_msg[offset + index].to_string());
+            let truncated = || MessageCompressionError::TruncatedResponseTopics { expected: response_topics_size, found: index };
+            response_topics.push_back(Self::decompress_string(fields.next().ok_or_else(truncated)?, "response topic")?);
+        }
+        if response_topics_size == 0 {
+            fields.next().ok_or(MessageCompressionError::TruncatedResponseTopics { expected: 0, found: 0 })?;
         }
-        offset += response_topics_size;
 
-        let sender = ser_msg[offset].to_string();
-        let text = ser_msg[offset + 1..].join(MESSAGE_SEPARATOR.to_string().as_str());
+        let sender = Self::decompress_string(
+            fields.next().ok_or_else(|| MessageCompressionError::FieldNotFound(String::from("sender")))?,
+            "sender"
+        )?;
+        // v1 frames never carried data: the rest of the frame is the text, NULs included
+        let text = fields.next().ok_or_else(|| MessageCompressionError::FieldNotFound(String::from("text")))?;
 
         Ok(Self {
             message_type,
             params,
             response_topics,
             sender,
-            text,
+            text: Self::decompress_string(text, "text")?,
+            ..Self::default()
         })
     }
 
+    /// reply
+    /// # Examples
+    /// ```rust
+    /// use std::collections::LinkedList;
+    /// use alfred_core::message::{Message, MessageType};
+    ///
+    /// let mut request = Message {
+    ///     text: String::from("ping"),
+    ///     response_topics: LinkedList::from([String::from("module.response")]),
+    ///     ..Message::default()
+    /// };
+    /// request.stamp();
+    /// let (topic, response) = request.reply(String::from("pong"), MessageType::Text).unwrap();
+    /// assert_eq!(topic, "module.response");
+    /// assert_eq!(response.correlation_id, request.id);
+    /// assert_eq!(response.hops, 1);
+    /// ```
     pub fn reply(&self, text: String, message_type: MessageType) -> Result<(String, Self), crate::error::Error> {
+        self.reply_with(Self {
+            message_type,
+            text,
+            ..Self::default()
+        })
+    }
+
+    /// Like [`Message::reply`], keeping type, text, data and params of the given response.
+    pub fn reply_with(&self, response: Self) -> Result<(String, Self), crate::error::Error> {
         let mut response_topics = self.response_topics.clone();
         let topic = response_topics.pop_front().ok_or(crate::error::Error::ReplyError)?;
         let response = Self {
-            text,
             response_topics,
             sender: self.sender.clone(),
-            message_type,
-            params: BTreeMap::default(),
+            correlation_id: self.conversation_id().to_string(),
+            hops: self.hops.saturating_add(1),
+            id: String::new(),
+            timestamp: 0,
+            ..response
         };
         Ok((topic, response))
     }
 
+    /// Identifier shared by all the messages of a conversation.
+    pub fn conversation_id(&self) -> &str {
+        if self.correlation_id.is_empty() { &self.id } else { &self.correlation_id }
+    }
+
 }
 
 impl fmt::Display for Message {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.compress())
+        write!(f, "{}", String::from_utf8_lossy(&self.compress()))
+    }
+}
+
+struct FrameReader<'a> {
+    frame: &'a [u8],
+}
+
+impl<'a> FrameReader<'a> {
+    const fn is_empty(&self) -> bool {
+        self.frame.is_empty()
+    }
+
+    fn read_byte(&mut self, field: &str) -> Result<u8, MessageCompressionError> {
+        let (&byte, frame) = self.frame.split_first()
+            .ok_or_else(|| MessageCompressionError::FieldNotFound(field.to_string()))?;
+        self.frame = frame;
+        Ok(byte)
+    }
+
+    fn read_number(&mut self, field: &str) -> Result<u64, MessageCompressionError> {
+        let mut number: u64 = 0;
+        for shift in (0..u64::BITS).step_by(7) {
+            let byte = self.read_byte(field)?;
+            let value = u64::from(byte & 0x7F);
+            if value.leading_zeros() < shift {
+                return Err(MessageCompressionError::BadCount(field.to_string()));
+            }
+            number |= value << shift;
+            if byte & 0x80 == 0 {
+                return Ok(number);
+            }
+        }
+        Err(MessageCompressionError::BadCount(field.to_string()))
+    }
+
+    fn read_count(&mut self, field: &str) -> Result<usize, MessageCompressionError> {
+        usize::try_from(self.read_number(field)?).map_err(|_| MessageCompressionError::BadCount(field.to_string()))
+    }
+
+    fn read_field(&mut self, field: &str) -> Result<&'a [u8], MessageCompressionError> {
+        let len = self.read_count(field)?;
+        let (value, frame) = self.frame.split_at_checked(len)
+            .ok_or_else(|| MessageCompressionError::TruncatedField(field.to_string()))?;
+        self.frame = frame;
+        Ok(value)
+    }
+
+    fn read_string(&mut self, field: &str) -> Result<String, MessageCompressionError> {
+        Message::decompress_string(self.read_field(field)?, field)
     }
 }
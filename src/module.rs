@@ -1,15 +1,27 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
 use clap::Command;
+use log::{debug, error, warn};
+use tokio::task::JoinHandle;
+use tokio::time::MissedTickBehavior;
 use crate::config::Config;
+use crate::dead_letter::DeadLetter;
+use crate::durable::{self, DurableSubscription, DURABLE_SUBSCRIBE_TOPIC, REPLAY_TOPIC_PARAM};
 use crate::error::Error;
-use crate::message::{Message, MessageType};
-use crate::connection::{Connection, MODULE_INFO_TOPIC_REQUEST, MODULE_INFO_TOPIC_RESPONSE, TOPIC_PREFIX};
+use crate::handler::{self, Handler, HandlerResult};
+use crate::message::Message;
+use crate::module_info::ModuleInfo;
+use crate::transport::Transport;
+use crate::connection::{Connection, MODULE_HEARTBEAT_TOPIC, MODULE_INFO_TOPIC_REQUEST, MODULE_INFO_TOPIC_RESPONSE};
 
 pub struct ModuleDetails {
     module_name: &'static str,
     version: &'static str,
     config: Option<Config>,
-    capabilities: BTreeMap<String, String>
+    capabilities: BTreeMap<String, String>,
+    transport: Option<Arc<dyn Transport>>
 }
 impl ModuleDetails {
     pub fn builder() -> ModuleDetailsBuilder {
@@ -23,7 +35,8 @@ pub struct ModuleDetailsBuilder {
     module_name: &'static str,
     version: &'static str,
     config: Option<Config>,
-    capabilities: BTreeMap<String, String>
+    capabilities: BTreeMap<String, String>,
+    transport: Option<Arc<dyn Transport>>
 }
 
 impl ModuleDetailsBuilder {
@@ -32,7 +45,8 @@ impl ModuleDetailsBuilder {
             module_name: "",
             version: "",
             config: None,
-            capabilities: BTreeMap::new()
+            capabilities: BTreeMap::new(),
+            transport: None
         }
     }
     pub const fn module_name(mut self, module_name: &'static str) -> Self {
@@ -51,22 +65,44 @@ impl ModuleDetailsBuilder {
         self.capabilities = capabilities;
         self
     }
+    /// Connects the module through `transport` instead of the daemon.
+    /// Command line arguments are not parsed and, without a config, the default one is used.
+    pub fn transport(mut self, transport: Arc<dyn Transport>) -> Self {
+        self.transport = Some(transport);
+        self
+    }
     pub fn build(self) -> ModuleDetails {
         ModuleDetails {
             module_name: self.module_name,
-            version: self.module_name,
+            version: self.version,
             config: self.config,
-            capabilities: self.capabilities
+            capabilities: self.capabilities,
+            transport: self.transport
         }
     }
 }
 
+/// Task sending the heartbeats of a module, stopped with the module.
+struct Heartbeat(JoinHandle<()>);
+
+impl Drop for Heartbeat {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
 pub struct AlfredModule {
     pub module_name: String,
     pub version: String,
     pub config: Config,
     pub connection: Connection,
-    pub capabilities: BTreeMap<String, String> // TODO: change to HashMap<&'static str, &'static str>
+    pub capabilities: BTreeMap<String, String>, // TODO: change to HashMap<&'static str, &'static str>
+    /// Topics subscribed with [`AlfredModule::listen`], reported in the module info
+    pub topics: BTreeSet<String>,
+    handlers: Vec<(String, Handler)>,
+    /// Whether the messages only received for the prefix of a subscription are dead-lettered too
+    dead_letter_prefixed: bool,
+    heartbeat: Option<Heartbeat>
 }
 
 impl AlfredModule {
@@ -84,51 +120,244 @@ impl AlfredModule {
             module_name,
             version,
             config: None,
-            capabilities: BTreeMap::new()
+            capabilities: BTreeMap::new(),
+            transport: None
         };
         Self::new_with_details(module_config).await
     }
 
     pub async fn new_with_details(module_details: ModuleDetails) -> Result<Self, Error> {
-        Self::manage_args(module_details.module_name, module_details.version);
-        let config = module_details.config.unwrap_or_else(|| Config::read(Some(module_details.module_name)));
         let capabilities = module_details.capabilities;
-        let mut connection = Connection::new(&config).await?;
+        let (config, connection) = if let Some(transport) = module_details.transport {
+            let config = module_details.config.unwrap_or_default();
+            let connection = Connection::with_transport(&config, transport).await?;
+            (config, connection)
+        } else {
+            Self::manage_args(module_details.module_name, module_details.version);
+            let config = module_details.config.unwrap_or_else(|| Config::read(Some(module_details.module_name)));
+            let connection = Connection::new(&config).await?;
+            (config, connection)
+        };
         connection.listen(MODULE_INFO_TOPIC_REQUEST).await?;
-        let alfred_module = Self {
+        connection.listen(&durable::replay_topic(module_details.module_name)).await?;
+        let mut alfred_module = Self {
             module_name: module_details.module_name.to_string(),
             version: module_details.version.to_string(),
             config,
             connection,
-            capabilities
+            capabilities,
+            topics: BTreeSet::new(),
+            handlers: Vec::new(),
+            dead_letter_prefixed: false,
+            heartbeat: None
         };
         alfred_module.send(MODULE_INFO_TOPIC_RESPONSE, &alfred_module.get_info_message()).await?;
+        if alfred_module.config.alfred.heartbeat_interval > 0 {
+            let interval = Duration::from_millis(alfred_module.config.alfred.heartbeat_interval);
+            let task = tokio::spawn(Self::send_heartbeats(alfred_module.connection.clone(), alfred_module.get_heartbeat_message(), interval));
+            alfred_module.heartbeat = Some(Heartbeat(task));
+        }
         Ok(alfred_module)
     }
 
-    pub fn get_info_message(&self) -> Message {
+    /// Publishes `heartbeat` on [`MODULE_HEARTBEAT_TOPIC`] every `interval`, the module info
+    /// having just announced the module.
+    async fn send_heartbeats(connection: Connection, heartbeat: Message, interval: Duration) {
+        let mut interval = tokio::time::interval(interval);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            if let Err(e) = connection.send(MODULE_HEARTBEAT_TOPIC, &heartbeat).await {
+                warn!("Unable to send the heartbeat: {e}");
+            }
+        }
+    }
+
+    pub fn get_heartbeat_message(&self) -> Message {
         Message {
             text: self.module_name.clone(),
-            message_type: MessageType::ModuleInfo,
-            params: self.capabilities.clone(),
+            sender: self.module_name.clone(),
+            params: BTreeMap::from([(String::from("version"), self.version.clone())]),
             ..Message::default()
         }
     }
 
+    pub fn info(&self) -> ModuleInfo {
+        ModuleInfo {
+            name: self.module_name.clone(),
+            version: self.version.clone(),
+            lib_version: Self::get_lib_version().to_string(),
+            topics: self.topics.clone(),
+            capabilities: self.capabilities.clone()
+        }
+    }
+
+    pub fn get_info_message(&self) -> Message {
+        self.info().to_message()
+    }
+
     pub async fn listen(&mut self, topic: &str) -> Result<(), Error> {
-        self.capabilities.insert(String::from(TOPIC_PREFIX), String::from(topic));
-        self.connection.listen(topic).await
+        self.connection.listen(topic).await?;
+        self.topics.insert(topic.to_string());
+        Ok(())
+    }
+
+    /// Like [`AlfredModule::listen`], asking the queue bin to keep the messages of `topic`
+    /// while the module is offline, with its default limits. They are replayed to this module only,
+    /// and received on their original topic by [`AlfredModule::receive`].
+    pub async fn listen_durable(&mut self, topic: &str) -> Result<(), Error> {
+        self.listen(topic).await?;
+        let subscription = DurableSubscription { module: self.module_name.clone(), topic: topic.to_string(), max_size: None, max_age: None };
+        self.send(DURABLE_SUBSCRIBE_TOPIC, &subscription.to_message()).await
     }
 
     pub async fn receive(&self) -> Result<(String, Message), Error> {
-        self.connection.receive(&self.module_name, &self.capabilities).await
+        loop {
+            let (mut topic, mut message) = self.connection.receive_all().await?;
+            if topic == durable::replay_topic(&self.module_name) {
+                // stored by the queue bin while the module was offline: received on its original topic
+                let Some(original_topic) = message.params.remove(REPLAY_TOPIC_PARAM) else {
+                    warn!("Dropping replayed message {} without topic", message.id);
+                    continue;
+                };
+                topic = original_topic;
+            }
+            if topic != MODULE_INFO_TOPIC_REQUEST { return Ok((topic, message)); }
+            debug!("Received info request. Replying...");
+            self.connection.send_module_info(&self.info()).await?;
+        }
     }
 
     pub async fn send(&self, topic: &str, message: &Message) -> Result<(), Error> {
         self.connection.send(topic, message).await
     }
 
+    /// Publishes the message received on `topic` on the dead-letter topic, as this module gives up on it.
+    pub async fn send_dead_letter(&self, topic: &str, message: &Message, reason: &str) -> Result<(), Error> {
+        self.connection.send_dead_letter(&self.dead_letter(topic, message, reason)).await
+    }
+
+    fn dead_letter(&self, topic: &str, message: &Message, reason: &str) -> DeadLetter {
+        DeadLetter { topic: topic.to_string(), reason: reason.to_string(), module: self.module_name.clone(), message: message.clone() }
+    }
+
     pub async fn send_event(&mut self, publisher_name: &str, event_name: &str, message: &Message) -> Result<(), Error> {
         self.connection.send_event(publisher_name, event_name, message).await
     }
-}
\ No newline at end of file
+
+    /// See [`Connection::send_reliable`].
+    pub async fn send_reliable(&self, topic: &str, message: &Message) -> Result<(), Error> {
+        self.connection.send_reliable(topic, message).await
+    }
+
+    pub async fn request(&self, topic: &str, message: &Message, timeout: Duration) -> Result<Message, Error> {
+        self.connection.request(topic, message, timeout).await
+    }
+
+    /// See [`Connection::discover_modules`].
+    pub async fn discover_modules(&self, timeout: Duration) -> Result<Vec<ModuleInfo>, Error> {
+        self.connection.discover_modules(timeout).await
+    }
+
+    /// See [`Connection::discover_modules_with_capability`].
+    pub async fn discover_modules_with_capability(&self, capability: &str, timeout: Duration) -> Result<Vec<ModuleInfo>, Error> {
+        self.connection.discover_modules_with_capability(capability, timeout).await
+    }
+
+    /// Registers a handler for the topics matching `pattern` (see [`handler::topic_matches`])
+    /// and subscribes to them. Handlers are executed by [`AlfredModule::run`].
+    pub async fn on<F, Fut>(&mut self, pattern: &str, handler: F) -> Result<(), Error>
+    where
+        F: Fn(String, Message) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = HandlerResult> + Send + 'static,
+    {
+        self.listen(handler::subscription(pattern)).await?;
+        self.handlers.push((pattern.to_string(), handler::handler(handler)));
+        Ok(())
+    }
+
+    /// Also publishes on the dead-letter topic the messages without handler only received for the prefix
+    /// of a subscription (e.g. `chat.response` for `on("chat")`), which [`AlfredModule::run`] drops otherwise.
+    /// Meant for the modules which must not lose a topic, like a router without route for it.
+    pub const fn dead_letter_prefixed(&mut self) {
+        self.dead_letter_prefixed = true;
+    }
+
+    /// Receives messages forever, running the matching handlers concurrently.
+    /// A failing handler is logged without affecting the others; replies returned by the handlers
+    /// are published using [`Message::reply_with`].
+    /// Messages on a listened topic without handler, failing the handler, or whose reply cannot be sent
+    /// for lack of response topic are published on the dead-letter topic; the other messages received
+    /// through a subscription prefix (e.g. `chat.response` for `on("chat")`) are dropped,
+    /// unless [`AlfredModule::dead_letter_prefixed`] is set.
+    pub async fn run(&self) -> Result<(), Error> {
+        self.dispatch(false).await
+    }
+
+    /// Like [`AlfredModule::run`], waiting for the handlers of each message before receiving the next one,
+    /// so that messages are handled in the order they were received (e.g. to forward them).
+    pub async fn run_sequential(&self) -> Result<(), Error> {
+        self.dispatch(true).await
+    }
+
+    async fn dispatch(&self, sequential: bool) -> Result<(), Error> {
+        loop {
+            let (topic, message) = self.receive().await?;
+            let mut handled = false;
+            for (_, handler) in self.handlers.iter().filter(|(pattern, _)| handler::topic_matches(pattern, &topic)) {
+                handled = true;
+                let dead_letter = self.dead_letter(&topic, &message, "");
+                let handling = Self::handle(self.connection.clone(), handler.clone(), dead_letter);
+                if sequential {
+                    handling.await;
+                } else {
+                    tokio::spawn(handling);
+                }
+            }
+            if handled { continue; }
+            if !self.dead_letter_prefixed && !self.topics.contains(&topic) {
+                // e.g. `chat.response` received through the `chat` subscription of `on("chat")`
+                debug!("Dropping message on topic {topic}, only received for the prefix of a subscription");
+                continue;
+            }
+            debug!("No handler found for topic {topic}");
+            if let Err(e) = self.send_dead_letter(&topic, &message, "No handler found").await {
+                error!("Error sending dead letter for topic {topic}: {e}");
+            }
+        }
+    }
+
+    /// Runs the handler on the message of `dead_letter`, which is published if the message is lost.
+    async fn handle(connection: Connection, handler: Handler, mut dead_letter: DeadLetter) {
+        let topic = dead_letter.topic.clone();
+        let reply = match handler(topic.clone(), dead_letter.message.clone()).await {
+            Ok(Some(reply)) => reply,
+            Ok(None) => return,
+            Err(e) => {
+                error!("Error handling message on topic {topic}: {e}");
+                dead_letter.reason = format!("Handler failed: {e}");
+                return Self::send_dead_letter_from(&connection, &dead_letter).await;
+            }
+        };
+        match dead_letter.message.reply_with(reply.clone()) {
+            Ok((reply_topic, reply)) => {
+                if let Err(e) = connection.send(&reply_topic, &reply).await {
+                    error!("Error sending reply to topic {reply_topic}: {e}");
+                }
+            },
+            Err(e) => {
+                warn!("Unable to reply to the message received on topic {topic}: {e}");
+                dead_letter.reason = format!("Unable to reply: {e}");
+                dead_letter.message = reply;
+                Self::send_dead_letter_from(&connection, &dead_letter).await;
+            }
+        }
+    }
+
+    async fn send_dead_letter_from(connection: &Connection, dead_letter: &DeadLetter) {
+        if let Err(e) = connection.send_dead_letter(dead_letter).await {
+            error!("Error sending dead letter for topic {}: {e}", dead_letter.topic);
+        }
+    }
+}
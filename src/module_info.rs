@@ -0,0 +1,88 @@
+use std::collections::{BTreeMap, BTreeSet};
+use serde_derive::{Deserialize, Serialize};
+use crate::message::{Message, MessageType};
+
+/// Prefix of the params reserved to the module info; the other params are the capabilities.
+pub const RESERVED_PARAM_PREFIX: &str = "module.";
+const VERSION_PARAM: &str = "module.version";
+const LIB_VERSION_PARAM: &str = "module.lib_version";
+/// Prefix of the params naming the subscribed topics, one per topic, as topics may contain any character
+const TOPIC_PARAM_PREFIX: &str = "module.topic.";
+
+/// Description of a module, as announced on [`crate::connection::MODULE_INFO_TOPIC_RESPONSE`].
+///
+/// It is sent as a [`MessageType::ModuleInfo`] message: the name in the text, the capabilities
+/// in the params, along with the versions and the topics in params starting with [`RESERVED_PARAM_PREFIX`].
+/// # Examples
+/// ```rust
+/// use alfred_core::module_info::ModuleInfo;
+///
+/// let info = ModuleInfo {
+///     name: "whisper".to_string(),
+///     version: "1.0.0".to_string(),
+///     lib_version: ModuleInfo::default().lib_version,
+///     topics: ["stt.request".to_string(), "stt.config".to_string()].into(),
+///     capabilities: [("speech-to-text".to_string(), "it,en".to_string())].into()
+/// };
+/// let parsed = ModuleInfo::from_message(&info.to_message()).unwrap();
+/// assert_eq!(parsed, info);
+/// assert!(parsed.has_capability("speech-to-text"));
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ModuleInfo {
+    pub name: String,
+    pub version: String,
+    /// Version of alfred-core used by the module
+    pub lib_version: String,
+    pub topics: BTreeSet<String>,
+    pub capabilities: BTreeMap<String, String>
+}
+
+impl Default for ModuleInfo {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            version: String::new(),
+            lib_version: env!("CARGO_PKG_VERSION").to_string(),
+            topics: BTreeSet::new(),
+            capabilities: BTreeMap::new()
+        }
+    }
+}
+
+impl ModuleInfo {
+    pub fn to_message(&self) -> Message {
+        let mut params = self.capabilities.clone();
+        params.insert(VERSION_PARAM.to_string(), self.version.clone());
+        params.insert(LIB_VERSION_PARAM.to_string(), self.lib_version.clone());
+        params.extend(self.topics.iter().map(|topic| (format!("{TOPIC_PARAM_PREFIX}{topic}"), String::new())));
+        Message {
+            text: self.name.clone(),
+            sender: self.name.clone(),
+            message_type: MessageType::ModuleInfo,
+            params,
+            ..Message::default()
+        }
+    }
+
+    /// Parses a module info message: `None` if it does not name a module.
+    /// Messages sent by older modules only carry the name and the capabilities.
+    pub fn from_message(message: &Message) -> Option<Self> {
+        if message.text.is_empty() { return None; }
+        let param = |key: &str| message.params.get(key).cloned().unwrap_or_default();
+        Some(Self {
+            name: message.text.clone(),
+            version: param(VERSION_PARAM),
+            lib_version: param(LIB_VERSION_PARAM),
+            topics: message.params.keys().filter_map(|key| key.strip_prefix(TOPIC_PARAM_PREFIX)).map(String::from).collect(),
+            capabilities: message.params.iter()
+                .filter(|(key, _)| !key.starts_with(RESERVED_PARAM_PREFIX))
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect()
+        })
+    }
+
+    pub fn has_capability(&self, capability: &str) -> bool {
+        self.capabilities.contains_key(capability)
+    }
+}
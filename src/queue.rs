@@ -0,0 +1,290 @@
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use log::{debug, info, warn};
+use serde_derive::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use crate::codec;
+use crate::connection::Connection;
+use crate::durable::{self, DurableSubscription, REPLAY_TOPIC_PARAM, REPLAY_TOPIC_PREFIX};
+use crate::error::Error;
+use crate::message::Message;
+
+const SUBSCRIPTIONS_FILENAME: &str = "subscriptions.toml";
+const QUEUE_EXTENSION: &str = "queue";
+pub const DEFAULT_MAX_SIZE: usize = 1000;
+/// Default maximum age (ms) of the messages kept
+pub const DEFAULT_MAX_AGE: u64 = 24 * 60 * 60 * 1000;
+/// Delay before replaying a message again after a failed attempt, doubled at each attempt.
+const REPLAY_RETRY_DELAY: Duration = Duration::from_millis(500);
+const REPLAY_RETRY_MAX_DELAY: Duration = Duration::from_secs(30);
+
+/// Durable subscriptions of a module and the limits of its queue.
+#[derive(Serialize, Deserialize, Clone)]
+struct QueueConfig {
+    topics: BTreeSet<String>,
+    max_size: usize,
+    max_age: u64
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct Subscriptions {
+    queues: BTreeMap<String, QueueConfig>
+}
+
+/// A message kept for an offline module.
+#[derive(Clone)]
+struct StoredMessage {
+    topic: String,
+    message: Message,
+    /// Timestamp (ms) of the reception by the queue, the limits do not depend on the clock of the sender
+    received: u64
+}
+
+struct Queue {
+    config: QueueConfig,
+    messages: VecDeque<StoredMessage>,
+    online: bool,
+    replaying: bool
+}
+
+impl Queue {
+    fn matches(&self, topic: &str) -> bool {
+        self.config.topics.iter().any(|prefix| topic.starts_with(prefix.as_str()))
+    }
+
+    /// Drops the messages beyond the limits, returning whether some were dropped.
+    fn trim(&mut self) -> bool {
+        let count = self.messages.len();
+        let oldest = Message::now().saturating_sub(self.config.max_age);
+        self.messages.retain(|stored| stored.received >= oldest);
+        while self.messages.len() > self.config.max_size {
+            self.messages.pop_front();
+        }
+        self.messages.len() != count
+    }
+}
+
+/// Queues of the modules with durable subscriptions, kept by the queue bin while they are offline.
+///
+/// They are stored in a directory: the subscriptions in a TOML file and the messages
+/// of each module in a file of length-prefixed reception time, topic and message frames.
+/// # Examples
+/// ```rust
+/// use alfred_core::durable::DurableSubscription;
+/// use alfred_core::message::Message;
+/// use alfred_core::queue::Queues;
+///
+/// let dir = std::env::temp_dir().join(format!("alfred-queue-{}", Message::new_id()));
+/// let mut queues = Queues::load(dir.clone());
+/// let subscription = DurableSubscription { module: "heater".to_string(), topic: "heater.".to_string(), max_size: None, max_age: None };
+/// queues.subscribe(subscription);
+/// queues.set_online("heater", false);
+/// queues.store("heater.off", &Message::default());
+/// assert_eq!(Queues::load(dir.clone()).stored("heater").len(), 1);
+/// # std::fs::remove_dir_all(dir).unwrap();
+/// ```
+pub struct Queues {
+    dir: PathBuf,
+    queues: BTreeMap<String, Queue>
+}
+
+impl Queues {
+    /// Loads the queues saved in `dir`; their modules are considered offline until [`Queues::set_online`].
+    pub fn load(dir: PathBuf) -> Self {
+        let subscriptions: Subscriptions = fs::read_to_string(dir.join(SUBSCRIPTIONS_FILENAME)).ok()
+            .and_then(|contents| toml::from_str(&contents).map_err(|e| warn!("Ignoring invalid subscriptions: {e}")).ok())
+            .unwrap_or_default();
+        let queues = subscriptions.queues.into_iter().map(|(module, config)| {
+            let messages = read_messages(&dir.join(format!("{module}.{QUEUE_EXTENSION}")));
+            (module, Queue { config, messages, online: false, replaying: false })
+        }).collect();
+        Self { dir, queues }
+    }
+
+    fn queue_path(&self, module: &str) -> PathBuf {
+        self.dir.join(format!("{module}.{QUEUE_EXTENSION}"))
+    }
+
+    /// Topics of every durable subscription.
+    pub fn topics(&self) -> BTreeSet<String> {
+        self.queues.values().flat_map(|queue| queue.config.topics.iter().cloned()).collect()
+    }
+
+    /// Modules with durable subscriptions.
+    pub fn modules(&self) -> Vec<String> {
+        self.queues.keys().cloned().collect()
+    }
+
+    /// Messages stored for `module`, oldest first, with their topic.
+    pub fn stored(&self, module: &str) -> Vec<(String, Message)> {
+        self.queues.get(module)
+            .map(|queue| queue.messages.iter().map(|stored| (stored.topic.clone(), stored.message.clone())).collect())
+            .unwrap_or_default()
+    }
+
+    fn save_subscriptions(&self) {
+        let subscriptions = Subscriptions {
+            queues: self.queues.iter().map(|(module, queue)| (module.clone(), queue.config.clone())).collect()
+        };
+        let saved = fs::create_dir_all(&self.dir).map_err(Error::from)
+            .and_then(|()| toml::to_string(&subscriptions).map_err(|e| std::io::Error::other(e).into()))
+            .and_then(|contents| fs::write(self.dir.join(SUBSCRIPTIONS_FILENAME), contents).map_err(Error::from));
+        if let Err(e) = saved {
+            warn!("Unable to save the subscriptions: {e}");
+        }
+    }
+
+    fn save_messages(&self, module: &str) {
+        let Some(queue) = self.queues.get(module) else { return };
+        if let Err(e) = write_messages(&self.queue_path(module), queue.messages.iter(), false) {
+            warn!("Unable to save the queue of {module}: {e}");
+        }
+    }
+
+    /// Returns whether the topic was not subscribed yet.
+    pub fn subscribe(&mut self, subscription: DurableSubscription) -> bool {
+        let new_topic = !self.topics().contains(&subscription.topic);
+        let queue = self.queues.entry(subscription.module.clone()).or_insert_with(|| Queue {
+            config: QueueConfig { topics: BTreeSet::new(), max_size: DEFAULT_MAX_SIZE, max_age: DEFAULT_MAX_AGE },
+            messages: VecDeque::new(),
+            online: true,
+            replaying: false
+        });
+        queue.config.topics.insert(subscription.topic);
+        queue.config.max_size = subscription.max_size.unwrap_or(DEFAULT_MAX_SIZE);
+        queue.config.max_age = subscription.max_age.unwrap_or(DEFAULT_MAX_AGE);
+        self.save_subscriptions();
+        new_topic
+    }
+
+    /// Returns whether the topic is not subscribed anymore.
+    pub fn unsubscribe(&mut self, subscription: &DurableSubscription) -> bool {
+        if let Some(queue) = self.queues.get_mut(&subscription.module) {
+            queue.config.topics.remove(&subscription.topic);
+            if queue.config.topics.is_empty() {
+                self.queues.remove(&subscription.module);
+                let _ = fs::remove_file(self.queue_path(&subscription.module));
+            }
+        }
+        self.save_subscriptions();
+        !self.topics().contains(&subscription.topic)
+    }
+
+    /// Stores the message in the queues of the offline modules subscribed to its topic.
+    pub fn store(&mut self, topic: &str, message: &Message) {
+        // replays are private to their module, and already stored
+        if topic.strip_prefix(REPLAY_TOPIC_PREFIX).is_some_and(|rest| rest.starts_with('.')) { return; }
+        let stored = StoredMessage { topic: topic.to_string(), message: message.clone(), received: Message::now() };
+        let mut trimmed = Vec::new();
+        let dir = &self.dir;
+        for (module, queue) in self.queues.iter_mut().filter(|(_, queue)| !queue.online && queue.matches(topic)) {
+            debug!("Storing message {} on topic {topic} for {module}", message.id);
+            queue.messages.push_back(stored.clone());
+            if queue.trim() {
+                trimmed.push(module.clone());
+            } else if let Err(e) = write_messages(&dir.join(format!("{module}.{QUEUE_EXTENSION}")), [&stored].into_iter(), true) {
+                warn!("Unable to store the message for {module}: {e}");
+            }
+        }
+        for module in trimmed {
+            self.save_messages(&module);
+        }
+    }
+
+    /// Marks the module online or offline, returning whether the replay of its messages has to start.
+    pub fn set_online(&mut self, module: &str, online: bool) -> bool {
+        let Some(queue) = self.queues.get_mut(module) else { return false };
+        queue.online = online;
+        if !online || queue.replaying || queue.messages.is_empty() { return false; }
+        queue.replaying = true;
+        true
+    }
+
+    /// Oldest message to replay to the module, if still online.
+    fn next(&mut self, module: &str) -> Option<StoredMessage> {
+        let queue = self.queues.get_mut(module)?;
+        let trimmed = queue.trim();
+        let next = queue.messages.front().filter(|_| queue.online).cloned();
+        queue.replaying = next.is_some();
+        if trimmed { self.save_messages(module); }
+        next
+    }
+
+    /// Drops the message once replayed, unless it was trimmed meanwhile.
+    fn replayed(&mut self, module: &str, message: &Message) {
+        let Some(queue) = self.queues.get_mut(module) else { return };
+        if queue.messages.front().is_some_and(|front| front.message.id == message.id) {
+            queue.messages.pop_front();
+        }
+        self.save_messages(module);
+    }
+}
+
+/// Marks the module online or offline, replaying its stored messages on a new task when it is back.
+pub async fn set_online(connection: &Connection, queues: &Arc<Mutex<Queues>>, module: &str, online: bool) {
+    if !queues.lock().await.set_online(module, online) { return; }
+    info!("Replaying the messages stored for {module}");
+    tokio::spawn(replay(connection.clone(), queues.clone(), module.to_string()));
+}
+
+/// Publishes the stored messages of `module` again on its [`durable::replay_topic`], oldest first, while it is online.
+/// They are sent as reliable messages, and only dropped once acknowledged: a message which is not
+/// (e.g. the module did not connect again yet) is sent again with a backoff.
+async fn replay(connection: Connection, queues: Arc<Mutex<Queues>>, module: String) {
+    let mut delay = REPLAY_RETRY_DELAY;
+    loop {
+        let Some(StoredMessage { topic, mut message, .. }) = queues.lock().await.next(&module) else { return };
+        message.params.insert(REPLAY_TOPIC_PARAM.to_string(), topic.clone());
+        if let Err(e) = connection.send_reliable(&durable::replay_topic(&module), &message).await {
+            warn!("Unable to replay message {} on topic {topic} for {module}, retrying in {delay:?}: {e}", message.id);
+            tokio::time::sleep(delay).await;
+            delay = delay.saturating_mul(2).min(REPLAY_RETRY_MAX_DELAY);
+            continue;
+        }
+        delay = REPLAY_RETRY_DELAY;
+        queues.lock().await.replayed(&module, &message);
+    }
+}
+
+fn read_messages(path: &Path) -> VecDeque<StoredMessage> {
+    let Ok(contents) = fs::read(path) else { return VecDeque::new() };
+    let mut messages = VecDeque::new();
+    let mut rest = contents.as_slice();
+    while let Some((received, after_received)) = read_frame(rest) {
+        let Some((topic, after_topic)) = read_frame(after_received) else { break };
+        let Some((frame, after_frame)) = read_frame(after_topic) else { break };
+        match (<[u8; 8]>::try_from(received), String::from_utf8(topic.to_vec()), codec::decode(frame)) {
+            (Ok(received), Ok(topic), Ok(message)) => messages.push_back(StoredMessage { topic, message, received: u64::from_be_bytes(received) }),
+            _ => warn!("Skipping invalid message in {}", path.display())
+        }
+        rest = after_frame;
+    }
+    messages
+}
+
+fn read_frame(bytes: &[u8]) -> Option<(&[u8], &[u8])> {
+    let (size, rest) = bytes.split_first_chunk::<4>()?;
+    let size = usize::try_from(u32::from_be_bytes(*size)).ok()?;
+    (rest.len() >= size).then(|| rest.split_at(size))
+}
+
+fn write_messages<'a>(path: &Path, messages: impl Iterator<Item = &'a StoredMessage>, append: bool) -> Result<(), Error> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let file: File = OpenOptions::new().create(true).write(true).append(append).truncate(!append).open(path)?;
+    let mut writer = BufWriter::new(file);
+    for stored in messages {
+        for frame in [stored.received.to_be_bytes().as_slice(), stored.topic.as_bytes(), &stored.message.try_compress()?] {
+            let size = u32::try_from(frame.len()).map_err(|_| std::io::Error::from(std::io::ErrorKind::InvalidData))?;
+            writer.write_all(&size.to_be_bytes())?;
+            writer.write_all(frame)?;
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
@@ -0,0 +1,193 @@
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Arc;
+use log::{debug, warn};
+use tokio::sync::{mpsc, oneshot, Mutex};
+use crate::error::Error;
+use crate::message::Message;
+use crate::transport::Subscriber;
+
+type Received = Result<(String, Message), Error>;
+
+enum Command {
+    /// Subscribes to the topic; passive subscriptions do not acknowledge the reliable messages.
+    Listen(String, bool, oneshot::Sender<Result<(), Error>>),
+    Unlisten(String, oneshot::Sender<Result<(), Error>>),
+    Replace(Box<dyn Subscriber>, oneshot::Sender<Result<(), Error>>),
+    Observe(String, mpsc::UnboundedSender<Message>, oneshot::Sender<Result<(), Error>>),
+    Unobserve(String, oneshot::Sender<Result<(), Error>>),
+}
+
+/// Owns the subscriber on a background task, so subscriptions can change (and replies can be
+/// dispatched) while another task is waiting for messages.
+/// Subscriptions are recorded, so they can be replayed on a new subscriber after a reconnection.
+/// Observers get a copy of the messages of a topic prefix without them reaching the inbox,
+/// unless the topic is also subscribed with `listen`.
+pub(crate) struct Receiver {
+    commands: mpsc::UnboundedSender<Command>,
+    inbox: Mutex<mpsc::UnboundedReceiver<Received>>,
+    pending_replies: Arc<Mutex<HashMap<String, oneshot::Sender<Message>>>>,
+    /// Subscriptions which are not passive, see [`Receiver::acknowledges`]
+    active_subscriptions: Arc<Mutex<BTreeSet<String>>>,
+}
+
+impl Receiver {
+    pub(crate) fn spawn(subscriber: Box<dyn Subscriber>) -> Self {
+        let (commands, commands_rx) = mpsc::unbounded_channel();
+        let (inbox_tx, inbox) = mpsc::unbounded_channel();
+        let pending_replies = Arc::new(Mutex::new(HashMap::new()));
+        let active_subscriptions = Arc::new(Mutex::new(BTreeSet::new()));
+        tokio::spawn(Self::run(subscriber, commands_rx, inbox_tx, pending_replies.clone(), active_subscriptions.clone()));
+        Self { commands, inbox: Mutex::new(inbox), pending_replies, active_subscriptions }
+    }
+
+    async fn run(
+        mut subscriber: Box<dyn Subscriber>,
+        mut commands: mpsc::UnboundedReceiver<Command>,
+        inbox: mpsc::UnboundedSender<Received>,
+        pending_replies: Arc<Mutex<HashMap<String, oneshot::Sender<Message>>>>,
+        active_subscriptions: Arc<Mutex<BTreeSet<String>>>
+    ) {
+        let mut subscriptions = BTreeSet::new();
+        let mut observers: HashMap<String, Vec<mpsc::UnboundedSender<Message>>> = HashMap::new();
+        loop {
+            tokio::select! {
+                command = commands.recv() => match command {
+                    Some(Command::Listen(topic, passive, ack)) => {
+                        let result = subscriber.listen(&topic).await;
+                        if result.is_ok() {
+                            if !passive { active_subscriptions.lock().await.insert(topic.clone()); }
+                            subscriptions.insert(topic);
+                        }
+                        let _ = ack.send(result);
+                    },
+                    Some(Command::Unlisten(topic, ack)) => {
+                        active_subscriptions.lock().await.remove(&topic);
+                        subscriptions.remove(&topic);
+                        let result = if observers.contains_key(&topic) { Ok(()) } else { subscriber.unlisten(&topic).await };
+                        let _ = ack.send(result);
+                    },
+                    Some(Command::Observe(topic, observer, ack)) => {
+                        let already_listening = subscriptions.contains(&topic) || observers.contains_key(&topic);
+                        let result = if already_listening { Ok(()) } else { subscriber.listen(&topic).await };
+                        if result.is_ok() { observers.entry(topic).or_default().push(observer); }
+                        let _ = ack.send(result);
+                    },
+                    Some(Command::Unobserve(topic, ack)) => {
+                        let mut result = Ok(());
+                        if let Some(topic_observers) = observers.get_mut(&topic) {
+                            topic_observers.retain(|observer| !observer.is_closed());
+                            if topic_observers.is_empty() {
+                                observers.remove(&topic);
+                                if !subscriptions.contains(&topic) { result = subscriber.unlisten(&topic).await; }
+                            }
+                        }
+                        let _ = ack.send(result);
+                    },
+                    Some(Command::Replace(new_subscriber, ack)) => {
+                        subscriber = new_subscriber;
+                        let topics = subscriptions.iter().chain(observers.keys()).cloned().collect();
+                        let _ = ack.send(Self::resubscribe(subscriber.as_mut(), &topics).await);
+                    },
+                    None => break
+                },
+                received = subscriber.receive() => match received {
+                    Ok((topic, message)) => {
+                        if Self::notify_observers(&mut observers, &topic, &message)
+                            && !subscriptions.iter().any(|subscription| topic.starts_with(subscription.as_str())) {
+                            continue;
+                        }
+                        let reply_sender = pending_replies.lock().await.remove(&topic);
+                        if let Some(reply_sender) = reply_sender {
+                            debug!("Received reply on topic {topic}");
+                            if reply_sender.send(message).is_err() {
+                                warn!("Reply on topic {topic} arrived too late");
+                            }
+                        } else if inbox.send(Ok((topic, message))).is_err() {
+                            break;
+                        }
+                    },
+                    Err(error) => {
+                        let closed = matches!(error, Error::GetMessageError);
+                        if inbox.send(Err(error)).is_err() || closed { break; }
+                    }
+                }
+            }
+        }
+        debug!("Receiver stopped");
+    }
+
+    /// Sends a copy of the message to the observers of its topic, returning whether there are some.
+    fn notify_observers(observers: &mut HashMap<String, Vec<mpsc::UnboundedSender<Message>>>, topic: &str, message: &Message) -> bool {
+        let mut observed = false;
+        for (_, topic_observers) in observers.iter_mut().filter(|(prefix, _)| topic.starts_with(prefix.as_str())) {
+            observed = true;
+            topic_observers.retain(|observer| observer.send(message.clone()).is_ok());
+        }
+        observed
+    }
+
+    async fn resubscribe(subscriber: &mut dyn Subscriber, subscriptions: &BTreeSet<String>) -> Result<(), Error> {
+        debug!("Replaying {} subscriptions", subscriptions.len());
+        for topic in subscriptions {
+            subscriber.listen(topic).await?;
+        }
+        Ok(())
+    }
+
+    async fn execute(&self, command: impl FnOnce(oneshot::Sender<Result<(), Error>>) -> Command) -> Result<(), Error> {
+        let (ack, ack_rx) = oneshot::channel();
+        self.commands.send(command(ack)).map_err(|_| Error::ConnectionError)?;
+        ack_rx.await.map_err(|_| Error::ConnectionError)?
+    }
+
+    pub(crate) async fn listen(&self, topic: &str, passive: bool) -> Result<(), Error> {
+        self.execute(|ack| Command::Listen(topic.to_string(), passive, ack)).await
+    }
+
+    pub(crate) async fn unlisten(&self, topic: &str) -> Result<(), Error> {
+        self.execute(|ack| Command::Unlisten(topic.to_string(), ack)).await
+    }
+
+    /// Returns a channel receiving a copy of the messages published on `topic` until [`Receiver::unobserve`].
+    pub(crate) async fn observe(&self, topic: &str) -> Result<mpsc::UnboundedReceiver<Message>, Error> {
+        let (observer, messages) = mpsc::unbounded_channel();
+        self.execute(|ack| Command::Observe(topic.to_string(), observer, ack)).await?;
+        Ok(messages)
+    }
+
+    /// Removes the observers of `topic` whose channel has been dropped.
+    pub(crate) async fn unobserve(&self, topic: &str) -> Result<(), Error> {
+        self.execute(|ack| Command::Unobserve(topic.to_string(), ack)).await
+    }
+
+    /// Swaps the subscriber with `subscriber`, subscribing it to every topic of the previous one.
+    pub(crate) async fn replace(&self, subscriber: Box<dyn Subscriber>) -> Result<(), Error> {
+        self.execute(|ack| Command::Replace(subscriber, ack)).await
+    }
+
+    pub(crate) async fn receive(&self) -> Received {
+        self.inbox.lock().await.recv().await.ok_or(Error::GetMessageError)?
+    }
+
+    /// Routes the next message received on `topic` to the returned channel instead of the inbox.
+    pub(crate) async fn expect_reply(&self, topic: &str) -> oneshot::Receiver<Message> {
+        let (reply_sender, reply_receiver) = oneshot::channel();
+        self.pending_replies.lock().await.insert(topic.to_string(), reply_sender);
+        reply_receiver
+    }
+
+    pub(crate) async fn expects_reply(&self, topic: &str) -> bool {
+        self.pending_replies.lock().await.contains_key(topic)
+    }
+
+    /// Whether the reliable messages received on `topic` are to be acknowledged: it must match a subscription
+    /// which is neither passive nor the wildcard (the empty topic), i.e. one of the topics handled by the module.
+    pub(crate) async fn acknowledges(&self, topic: &str) -> bool {
+        self.active_subscriptions.lock().await.iter()
+            .any(|subscription| !subscription.is_empty() && topic.starts_with(subscription.as_str()))
+    }
+
+    pub(crate) async fn cancel_reply(&self, topic: &str) {
+        self.pending_replies.lock().await.remove(topic);
+    }
+}
@@ -0,0 +1,164 @@
+use std::borrow::Cow;
+use std::io::{self, ErrorKind, Read, Write};
+use std::time::Duration;
+use bytes::Bytes;
+use log::{debug, info};
+use crate::connection::{HANDSHAKE_PROBE_INTERVAL, PROBE_TOPIC_PREFIX};
+use crate::error::Error;
+use crate::message::Message;
+use crate::transport::{Frames, RawPublisher, RawSubscriber};
+
+/// Start of every recording, followed by the format version.
+const MAGIC: &[u8] = b"ALFREDREC";
+const FORMAT_VERSION: u8 = 1;
+const WILDCARD_TOPIC: &str = "";
+
+/// A message received on the bus: the time (ms) of its reception and its frames, as published.
+///
+/// Recordings store them one after the other as a big-endian `u64` timestamp, the `u32` number
+/// of frames, then the frames, each prefixed by its `u32` length.
+/// # Examples
+/// ```rust
+/// use alfred_core::bytes::Bytes;
+/// use alfred_core::recorder::Record;
+///
+/// let record = Record { timestamp: 1000, frames: vec![Bytes::from("topic"), Bytes::from_static(&[0xFF])] };
+/// let mut recording = Vec::new();
+/// record.write(&mut recording).unwrap();
+/// let read = Record::read(&mut recording.as_slice()).unwrap().unwrap();
+/// assert_eq!((read.timestamp, read.topic()), (1000, "topic".into()));
+/// assert_eq!(read.frames, record.frames);
+/// ```
+pub struct Record {
+    pub timestamp: u64,
+    pub frames: Frames
+}
+
+impl Record {
+    /// Topic of the message, with the invalid UTF-8 sequences replaced.
+    pub fn topic(&self) -> Cow<'_, str> {
+        self.frames.first().map_or(Cow::Borrowed(""), |topic| String::from_utf8_lossy(topic))
+    }
+
+    pub fn write(&self, writer: &mut impl Write) -> io::Result<()> {
+        writer.write_all(&self.timestamp.to_be_bytes())?;
+        writer.write_all(&size(self.frames.len())?.to_be_bytes())?;
+        for frame in &self.frames {
+            writer.write_all(&size(frame.len())?.to_be_bytes())?;
+            writer.write_all(frame)?;
+        }
+        Ok(())
+    }
+
+    /// Reads the next record, `None` at the end of the recording.
+    pub fn read(reader: &mut impl Read) -> io::Result<Option<Self>> {
+        let mut timestamp = [0; 8];
+        match reader.read_exact(&mut timestamp) {
+            Ok(()) => {},
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e)
+        }
+        let count = read_u32(reader)?;
+        // the sizes are not trusted to allocate: the frames grow as they are read
+        let mut frames = Frames::new();
+        for _ in 0..count {
+            let size = u64::from(read_u32(reader)?);
+            let mut frame = Vec::new();
+            if reader.by_ref().take(size).read_to_end(&mut frame)? as u64 != size {
+                return Err(ErrorKind::UnexpectedEof.into());
+            }
+            frames.push(Bytes::from(frame));
+        }
+        Ok(Some(Self { timestamp: u64::from_be_bytes(timestamp), frames }))
+    }
+}
+
+fn size(len: usize) -> io::Result<u32> {
+    u32::try_from(len).map_err(|_| io::Error::new(ErrorKind::InvalidInput, "frame too long"))
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_be_bytes(bytes))
+}
+
+/// Starts a recording, before its records.
+pub fn write_header(writer: &mut impl Write) -> io::Result<()> {
+    writer.write_all(MAGIC)?;
+    writer.write_all(&[FORMAT_VERSION])
+}
+
+/// Writes every message published on the bus to `writer`, as received, until the subscriber fails.
+/// The writer is flushed at every message, as the recording is usually stopped by a signal.
+pub async fn record(subscriber: &mut dyn RawSubscriber, writer: &mut (impl Write + Send)) -> Result<(), Error> {
+    write_header(writer)?;
+    writer.flush()?;
+    subscriber.listen(WILDCARD_TOPIC).await?;
+    loop {
+        let record = Record { timestamp: Message::now(), frames: subscriber.receive().await? };
+        debug!("Recording message on topic {}", record.topic());
+        record.write(writer)?;
+        writer.flush()?;
+    }
+}
+
+/// Publishes probes until one is received back through the broker, like the handshake of a
+/// connection: the frames published before both sockets are connected would be lost.
+async fn handshake(subscriber: &mut dyn RawSubscriber, publisher: &mut dyn RawPublisher, timeout: Duration) -> Result<(), Error> {
+    let topic = format!("{PROBE_TOPIC_PREFIX}.{}", Message::new_id());
+    let mut probe = Message::default();
+    probe.stamp();
+    let frames = vec![Bytes::from(topic.clone()), Bytes::from(probe.try_compress()?)];
+    subscriber.listen(&topic).await?;
+    let handshake = tokio::time::timeout(timeout, async {
+        loop {
+            publisher.send(frames.clone()).await?;
+            let attempt = tokio::time::Instant::now() + HANDSHAKE_PROBE_INTERVAL;
+            while let Ok(received) = tokio::time::timeout_at(attempt, subscriber.receive()).await {
+                if received?.first() == frames.first() { return Ok::<_, Error>(()); }
+            }
+        }
+    }).await;
+    subscriber.unlisten(&topic).await?;
+    handshake.map_err(|_| Error::HandshakeTimeout)?
+}
+
+/// Publishes a recording again, as recorded, returning the number of messages published.
+///
+/// The replay starts after a handshake with the broker through `subscriber`, failing after `handshake_timeout`.
+/// The original timing is followed with a `speed` factor, 0 to publish without waiting;
+/// only the topics starting with one of `topics` are published, unless it is empty.
+pub async fn replay(
+    subscriber: &mut dyn RawSubscriber,
+    publisher: &mut dyn RawPublisher,
+    reader: &mut (impl Read + Send),
+    speed: f64,
+    topics: &[String],
+    handshake_timeout: Duration
+) -> Result<usize, Error> {
+    if !speed.is_finite() || speed < 0.0 {
+        return Err(io::Error::new(ErrorKind::InvalidInput, format!("invalid speed {speed}")).into());
+    }
+    let mut header = [0; MAGIC.len() + 1];
+    reader.read_exact(&mut header)?;
+    if !header.starts_with(MAGIC) || header[MAGIC.len()] != FORMAT_VERSION {
+        return Err(io::Error::new(ErrorKind::InvalidData, "not a recording").into());
+    }
+    handshake(subscriber, publisher, handshake_timeout).await?;
+    let mut previous = None;
+    let mut count = 0;
+    while let Some(record) = Record::read(reader)? {
+        let topic = record.frames.first().map_or(&[][..], |topic| topic.as_ref());
+        if !topics.is_empty() && !topics.iter().any(|prefix| topic.starts_with(prefix.as_bytes())) { continue; }
+        if let Some(previous) = previous.filter(|_| speed > 0.0) {
+            let delay = Duration::from_millis(record.timestamp.saturating_sub(previous)).as_secs_f64() / speed;
+            tokio::time::sleep(Duration::try_from_secs_f64(delay).unwrap_or(Duration::MAX)).await;
+        }
+        previous = Some(record.timestamp);
+        publisher.send(record.frames).await?;
+        count += 1;
+    }
+    info!("{count} messages replayed");
+    Ok(count)
+}
@@ -0,0 +1,120 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use log::warn;
+use serde_derive::{Deserialize, Serialize};
+use crate::error::Error;
+use crate::message::{Message, MessageType};
+use crate::module_info::ModuleInfo;
+
+/// Requests answered by the registry bin with the known modules in the params of the reply (name -> version).
+/// With a [`CAPABILITY_PARAM`] param, only the modules offering it are listed.
+pub const LIST_TOPIC_REQUEST: &str = "registry.list";
+/// Requests answered by the registry bin with the info of the module named in the text.
+pub const LOOKUP_TOPIC_REQUEST: &str = "registry.lookup";
+pub const CAPABILITY_PARAM: &str = "capability";
+/// Added to the module info sent in the lookup replies: timestamp (ms) of the last info or heartbeat received
+pub const LAST_SEEN_PARAM: &str = "module.last_seen";
+/// Version sent in the heartbeats
+const VERSION_PARAM: &str = "version";
+
+#[derive(Serialize, Deserialize)]
+struct ModuleEntry {
+    info: ModuleInfo,
+    /// Timestamp (ms) of the last info or heartbeat received
+    last_seen: u64
+}
+
+/// Modules known by the registry bin, built from their info and heartbeats and saved as a TOML snapshot.
+/// # Examples
+/// ```rust
+/// use alfred_core::message::Message;
+/// use alfred_core::module_info::ModuleInfo;
+/// use alfred_core::registry::Directory;
+///
+/// let mut directory = Directory::default();
+/// let info = ModuleInfo {
+///     name: "whisper".to_string(),
+///     version: "1.0.0".to_string(),
+///     capabilities: [("speech-to-text".to_string(), "it".to_string())].into(),
+///     ..ModuleInfo::default()
+/// };
+/// directory.update_info(&info.to_message());
+/// assert_eq!(directory.list(Some("speech-to-text")).get("whisper"), Some(&"1.0.0".to_string()));
+/// assert!(directory.list(Some("text-to-speech")).is_empty());
+/// ```
+#[derive(Serialize, Deserialize, Default)]
+pub struct Directory {
+    modules: BTreeMap<String, ModuleEntry>,
+    #[serde(skip)]
+    changed: bool
+}
+
+impl Directory {
+    /// Loads the snapshot saved in `path`, starting empty if it is missing or invalid.
+    pub fn load(path: &Path) -> Self {
+        let Ok(contents) = fs::read_to_string(path) else { return Self::default() };
+        toml::from_str(&contents).unwrap_or_else(|e| {
+            warn!("Ignoring invalid snapshot {}: {e}", path.display());
+            Self::default()
+        })
+    }
+
+    pub fn save(&mut self, path: &Path) -> Result<(), Error> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, toml::to_string(self).map_err(std::io::Error::other)?)?;
+        self.changed = false;
+        Ok(())
+    }
+
+    /// Whether the directory changed since it was loaded or last saved.
+    pub const fn is_changed(&self) -> bool {
+        self.changed
+    }
+
+    fn last_seen(message: &Message) -> u64 {
+        if message.timestamp == 0 { Message::now() } else { message.timestamp }
+    }
+
+    pub fn update_info(&mut self, message: &Message) {
+        let Some(info) = ModuleInfo::from_message(message) else { return };
+        self.modules.insert(info.name.clone(), ModuleEntry { info, last_seen: Self::last_seen(message) });
+        self.changed = true;
+    }
+
+    /// Heartbeats of unknown modules only give their name and version, until they send their info.
+    pub fn update_heartbeat(&mut self, message: &Message) {
+        if message.text.is_empty() { return; }
+        let entry = self.modules.entry(message.text.clone()).or_insert_with(|| ModuleEntry {
+            info: ModuleInfo { name: message.text.clone(), lib_version: String::new(), ..ModuleInfo::default() },
+            last_seen: 0
+        });
+        if let Some(version) = message.params.get(VERSION_PARAM) {
+            entry.info.version.clone_from(version);
+        }
+        entry.last_seen = Self::last_seen(message);
+        self.changed = true;
+    }
+
+    /// Versions of the known modules, by name; only the ones offering `capability`, if any.
+    pub fn list(&self, capability: Option<&str>) -> BTreeMap<String, String> {
+        self.modules.iter()
+            .filter(|(_, entry)| capability.is_none_or(|capability| entry.info.has_capability(capability)))
+            .map(|(module_name, entry)| (module_name.clone(), entry.info.version.clone()))
+            .collect()
+    }
+
+    /// Info of the module, with its [`LAST_SEEN_PARAM`], or an [`MessageType::Error`] message if it is unknown.
+    pub fn lookup(&self, module_name: &str) -> Message {
+        self.modules.get(module_name).map_or_else(
+            || Message { text: format!("Unknown module {module_name}"), message_type: MessageType::Error, ..Message::default() },
+            |entry| {
+                let mut info = entry.info.to_message();
+                info.params.insert(LAST_SEEN_PARAM.to_string(), entry.last_seen.to_string());
+                info
+            }
+        )
+    }
+}
@@ -0,0 +1,114 @@
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+use log::{debug, info, warn};
+use tokio::sync::{watch, Mutex};
+use tokio::time::MissedTickBehavior;
+use crate::config::Config;
+use crate::connection::ConnectionStatus;
+use crate::error::Error;
+use crate::message::Message;
+use crate::receiver::Receiver;
+use crate::transport::{Publisher, Transport};
+
+/// Watches the broker on behalf of a `Connection`.
+///
+/// A probe is periodically published on a private topic: if it does not come back through the
+/// broker in time, the broker is considered lost. Both sockets are then re-created, with an
+/// exponential backoff, and the subscriptions are replayed on the new subscriber.
+/// The supervisor stops as soon as the connection is dropped.
+pub(crate) struct Supervisor {
+    receiver: Weak<Receiver>,
+    publisher: Weak<Mutex<Box<dyn Publisher>>>,
+    status: watch::Sender<ConnectionStatus>,
+    probe_topic: String,
+    transport: Arc<dyn Transport>,
+    probe_interval: Duration,
+    reconnect_delay: Duration,
+    reconnect_max_delay: Duration,
+}
+
+impl Supervisor {
+    pub(crate) fn spawn(
+        config: &Config,
+        receiver: &Arc<Receiver>,
+        publisher: &Arc<Mutex<Box<dyn Publisher>>>,
+        transport: Arc<dyn Transport>,
+        status: watch::Sender<ConnectionStatus>,
+        probe_topic: String
+    ) {
+        let supervisor = Self {
+            receiver: Arc::downgrade(receiver),
+            publisher: Arc::downgrade(publisher),
+            status,
+            probe_topic,
+            transport,
+            probe_interval: Duration::from_millis(config.alfred.probe_interval),
+            reconnect_delay: Duration::from_millis(config.alfred.reconnect_delay.max(1)),
+            reconnect_max_delay: Duration::from_millis(config.alfred.reconnect_max_delay.max(config.alfred.reconnect_delay)),
+        };
+        tokio::spawn(supervisor.run());
+    }
+
+    async fn run(self) {
+        let mut interval = tokio::time::interval(self.probe_interval);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            let (Some(receiver), Some(publisher)) = (self.receiver.upgrade(), self.publisher.upgrade()) else { break };
+            let alive = probe(&receiver, &publisher, &self.probe_topic, self.probe_interval).await;
+            // the connection must not be kept alive while the broker is down
+            drop((receiver, publisher));
+            if alive { continue; }
+            warn!("Connection to the broker lost. Reconnecting...");
+            let _ = self.status.send(ConnectionStatus::Disconnected);
+            match self.reconnect().await {
+                Ok(true) => {
+                    info!("Reconnected to the broker");
+                    let _ = self.status.send(ConnectionStatus::Connected);
+                    interval.reset();
+                },
+                Ok(false) => break,
+                Err(error) => {
+                    warn!("Unable to restore the connection to the broker: {error}");
+                    break;
+                }
+            }
+        }
+        debug!("Supervisor stopped");
+    }
+
+    /// Re-creates the sockets until the broker is back, `false` if the connection is dropped meanwhile.
+    async fn reconnect(&self) -> Result<bool, Error> {
+        let mut delay = self.reconnect_delay;
+        loop {
+            if self.receiver.strong_count() == 0 { return Ok(false); }
+            match tokio::time::timeout(delay, self.transport.connect()).await {
+                Ok(Ok((new_subscriber, new_publisher))) => {
+                    let (Some(receiver), Some(publisher)) = (self.receiver.upgrade(), self.publisher.upgrade()) else { return Ok(false) };
+                    receiver.replace(new_subscriber).await?;
+                    *publisher.lock().await = new_publisher;
+                    return Ok(true);
+                },
+                Ok(Err(error)) => debug!("Reconnection attempt failed: {error}"),
+                Err(_) => debug!("Reconnection attempt timed out")
+            }
+            debug!("Next reconnection attempt in {}ms", delay.as_millis());
+            tokio::time::sleep(delay).await;
+            delay = (delay * 2).min(self.reconnect_max_delay);
+        }
+    }
+}
+
+/// Publishes a probe on `probe_topic` and waits up to `timeout` for it to come back through the broker.
+pub(crate) async fn probe(receiver: &Receiver, publisher: &Mutex<Box<dyn Publisher>>, probe_topic: &str, timeout: Duration) -> bool {
+    let echo = receiver.expect_reply(probe_topic).await;
+    let mut probe = Message::default();
+    probe.stamp();
+    let alive = match publisher.lock().await.send(probe_topic, &probe).await {
+        Ok(()) => matches!(tokio::time::timeout(timeout, echo).await, Ok(Ok(_))),
+        Err(_) => false
+    };
+    receiver.cancel_reply(probe_topic).await;
+    alive
+}
@@ -0,0 +1,123 @@
+use std::future::Future;
+use std::pin::Pin;
+use bytes::Bytes;
+use crate::codec::CodecKind;
+use crate::config::Config;
+use crate::error::Error;
+use crate::message::Message;
+use crate::zmq_connection::{AlfredPublisher, AlfredSubscriber};
+
+pub type TransportFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T, Error>> + Send + 'a>>;
+/// Frames of a message as published: the topic, then the encoded message.
+pub type Frames = Vec<Bytes>;
+
+/// Receiving side of a transport. Subscriptions are topic prefixes.
+pub trait Subscriber: Send {
+    fn listen<'a>(&'a mut self, topic: &'a str) -> TransportFuture<'a, ()>;
+    fn unlisten<'a>(&'a mut self, topic: &'a str) -> TransportFuture<'a, ()>;
+    /// Waits for the next message. Must be cancel-safe: dropping the future loses no message.
+    fn receive(&mut self) -> TransportFuture<'_, (String, Message)>;
+}
+
+/// Sending side of a transport.
+pub trait Publisher: Send {
+    fn send<'a>(&'a mut self, topic: &'a str, message: &'a Message) -> TransportFuture<'a, ()>;
+}
+
+/// Receiving side of a transport delivering the frames as published, without decoding them
+/// (e.g. to record the bus): malformed messages, params and copies are all kept.
+pub trait RawSubscriber: Send {
+    fn listen<'a>(&'a mut self, topic: &'a str) -> TransportFuture<'a, ()>;
+    fn unlisten<'a>(&'a mut self, topic: &'a str) -> TransportFuture<'a, ()>;
+    /// Waits for the frames of the next message. Must be cancel-safe, like [`Subscriber::receive`].
+    fn receive(&mut self) -> TransportFuture<'_, Frames>;
+}
+
+/// Sending side of a transport publishing frames as they are.
+pub trait RawPublisher: Send {
+    fn send(&mut self, frames: Frames) -> TransportFuture<'_, ()>;
+}
+
+/// Creates the subscriber and publisher used by a `Connection`.
+///
+/// `connect` is called again to re-create both sides when the broker is lost.
+pub trait Transport: Send + Sync {
+    fn connect(&self) -> TransportFuture<'_, (Box<dyn Subscriber>, Box<dyn Publisher>)>;
+    /// Creates a subscriber and a publisher of raw frames, which are not reconnected when the broker is lost.
+    fn connect_raw(&self) -> TransportFuture<'_, (Box<dyn RawSubscriber>, Box<dyn RawPublisher>)>;
+}
+
+/// Transport connecting to the broker (the `daemon` bin) through zeromq sockets.
+pub struct ZmqTransport {
+    sub_url: String,
+    pub_url: String,
+    codec: CodecKind
+}
+
+impl ZmqTransport {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            sub_url: config.get_alfred_sub_url(),
+            pub_url: config.get_alfred_pub_url(),
+            codec: config.alfred.codec
+        }
+    }
+}
+
+impl Transport for ZmqTransport {
+    fn connect(&self) -> TransportFuture<'_, (Box<dyn Subscriber>, Box<dyn Publisher>)> {
+        Box::pin(async move {
+            let subscriber: Box<dyn Subscriber> = Box::new(AlfredSubscriber::new(self.sub_url.as_str()).await?);
+            let publisher: Box<dyn Publisher> = Box::new(AlfredPublisher::new(self.pub_url.as_str(), self.codec.codec()?).await?);
+            Ok((subscriber, publisher))
+        })
+    }
+
+    fn connect_raw(&self) -> TransportFuture<'_, (Box<dyn RawSubscriber>, Box<dyn RawPublisher>)> {
+        Box::pin(async move {
+            let subscriber: Box<dyn RawSubscriber> = Box::new(AlfredSubscriber::new(self.sub_url.as_str()).await?);
+            let publisher: Box<dyn RawPublisher> = Box::new(AlfredPublisher::new(self.pub_url.as_str(), self.codec.codec()?).await?);
+            Ok((subscriber, publisher))
+        })
+    }
+}
+
+impl Subscriber for AlfredSubscriber {
+    fn listen<'a>(&'a mut self, topic: &'a str) -> TransportFuture<'a, ()> {
+        Box::pin(Self::listen(self, topic))
+    }
+
+    fn unlisten<'a>(&'a mut self, topic: &'a str) -> TransportFuture<'a, ()> {
+        Box::pin(Self::unlisten(self, topic))
+    }
+
+    fn receive(&mut self) -> TransportFuture<'_, (String, Message)> {
+        Box::pin(Self::receive(self))
+    }
+}
+
+impl Publisher for AlfredPublisher {
+    fn send<'a>(&'a mut self, topic: &'a str, message: &'a Message) -> TransportFuture<'a, ()> {
+        Box::pin(Self::send(self, topic, message))
+    }
+}
+
+impl RawSubscriber for AlfredSubscriber {
+    fn listen<'a>(&'a mut self, topic: &'a str) -> TransportFuture<'a, ()> {
+        Box::pin(Self::listen(self, topic))
+    }
+
+    fn unlisten<'a>(&'a mut self, topic: &'a str) -> TransportFuture<'a, ()> {
+        Box::pin(Self::unlisten(self, topic))
+    }
+
+    fn receive(&mut self) -> TransportFuture<'_, Frames> {
+        Box::pin(Self::receive_frames(self))
+    }
+}
+
+impl RawPublisher for AlfredPublisher {
+    fn send(&mut self, frames: Frames) -> TransportFuture<'_, ()> {
+        Box::pin(Self::send_frames(self, frames))
+    }
+}
@@ -1,6 +1,7 @@
 use bytes::Bytes;
 use log::debug;
 use zeromq::{Socket, SocketRecv, SocketSend, ZmqMessage};
+use crate::codec::{self, MessageCodec};
 use crate::error::Error;
 use crate::message::Message;
 
@@ -16,8 +17,19 @@ impl AlfredSubscriber {
         Ok(Self { subscriber })
     }
 
-    fn get_slice_from_message(zmq_message: &ZmqMessage, index: usize) -> Result<String, Error> {
-        String::from_utf8(zmq_message.get(index).ok_or(Error::GetMessageError)?.to_vec()).or(Err(Error::ConversionError))
+    /// Decodes the topic and the message, returning a `MalformedMessage` error with the raw frames on failure.
+    fn decode(zmq_message: ZmqMessage) -> Result<(String, Message), Error> {
+        let topic = zmq_message.get(0).map(|topic| String::from_utf8(topic.to_vec()));
+        let decoded = match (&topic, zmq_message.get(1)) {
+            (Some(Ok(topic)), Some(frame)) => codec::decode(frame).map(|message| (topic.clone(), message)).map_err(|e| e.to_string()),
+            (Some(Err(_)), _) => Err(String::from("topic is not valid UTF-8")),
+            (None | Some(Ok(_)), _) => Err(String::from("message frame missing"))
+        };
+        decoded.map_err(|reason| Error::MalformedMessage {
+            topic: zmq_message.get(0).map(|topic| String::from_utf8_lossy(topic).to_string()).unwrap_or_default(),
+            frames: zmq_message.into_vec(),
+            reason
+        })
     }
 
     pub(crate) async fn listen(&mut self, topic: &str) -> Result<(), Error> {
@@ -25,40 +37,60 @@ impl AlfredSubscriber {
         self.subscriber.subscribe(topic).await.map_err(|_| Error::SubscribeError(topic.to_string()))
     }
 
-    pub(crate) async fn receive(&mut self) -> Result<(String, Message), Error> {
+    pub(crate) async fn unlisten(&mut self, topic: &str) -> Result<(), Error> {
+        debug!("Unsubscribing from topic {topic}");
+        self.subscriber.unsubscribe(topic).await.map_err(|_| Error::SubscribeError(topic.to_string()))
+    }
+
+    /// Receives the frames of the next message, without decoding them.
+    pub(crate) async fn receive_frames(&mut self) -> Result<Vec<Bytes>, Error> {
         let zmq_message = self.subscriber.recv().await.map_err(|_| Error::GetMessageError)?;
         debug!("New message received.");
-        let topic_string = Self::get_slice_from_message(&zmq_message, 0)?;
-        let msg_string = Self::get_slice_from_message(&zmq_message, 1)?;
+        Ok(zmq_message.into_vec())
+    }
 
-        let message = Message::decompress(msg_string.as_str())?;
-        debug!("{topic_string}: {message}");
-        Ok((topic_string, message))
+    pub(crate) async fn receive(&mut self) -> Result<(String, Message), Error> {
+        let zmq_message = self.subscriber.recv().await.map_err(|_| Error::GetMessageError)?;
+        debug!("New message received.");
+        let (topic, message) = Self::decode(zmq_message)?;
+        debug!("{topic}: {message}");
+        Ok((topic, message))
     }
 }
 
 pub struct AlfredPublisher {
-    publisher: zeromq::PubSocket
+    publisher: zeromq::PubSocket,
+    codec: Box<dyn MessageCodec>
 }
 
 impl AlfredPublisher {
 
-    pub(crate) async fn new(url: &str) -> Result<Self, Error> {
+    pub(crate) async fn new(url: &str, codec: Box<dyn MessageCodec>) -> Result<Self, Error> {
         let mut publisher = zeromq::PubSocket::new();
         publisher.connect(url).await?;
-        Ok(Self { publisher })
+        Ok(Self { publisher, codec })
     }
 
-    async fn publish_str(&mut self, topic: &str, message: &str) -> Result<(), Error> {
+    async fn publish_bytes(&mut self, topic: &str, message: Vec<u8>) -> Result<(), Error> {
         let topic_bytes = Bytes::from(topic.to_string());
-        let message_bytes = Bytes::from(message.to_string());
-        let zmq_message = vec![topic_bytes, message_bytes].try_into().or(Err(Error::ConversionError))?;
-        debug!(" > {topic}: {message}");
-        self.publisher.send(zmq_message).await.map_err(|_| Error::PublishError(topic.to_string(), message.to_string()))
+        let message_bytes = Bytes::from(message);
+        let zmq_message = vec![topic_bytes, message_bytes.clone()].try_into().or(Err(Error::ConversionError))?;
+        let message_str = String::from_utf8_lossy(&message_bytes);
+        debug!(" > {topic}: {message_str}");
+        self.publisher.send(zmq_message).await.map_err(|_| Error::PublishError(topic.to_string(), message_str.to_string()))
+    }
+
+    /// Publishes frames as they are, the topic first.
+    pub(crate) async fn send_frames(&mut self, frames: Vec<Bytes>) -> Result<(), Error> {
+        let topic = frames.first().map(|topic| String::from_utf8_lossy(topic).to_string()).unwrap_or_default();
+        let zmq_message = ZmqMessage::try_from(frames).or(Err(Error::ConversionError))?;
+        debug!(" > {topic}: raw frames");
+        self.publisher.send(zmq_message).await.map_err(|_| Error::PublishError(topic, String::from("raw frames")))
     }
 
     pub(crate) async fn send(&mut self, topic: &str, message: &Message) -> Result<(), Error> {
         debug!("Publishing message {message} to topic {topic}...");
-        self.publish_str(topic, message.compress().as_str()).await
+        let frame = self.codec.encode(message)?;
+        self.publish_bytes(topic, frame).await
     }
 }
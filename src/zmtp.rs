@@ -0,0 +1,164 @@
+//! Minimal ZMTP 3.0 implementation (NULL security mechanism), as spoken by the `zeromq` crate,
+//! used by the broker to talk to the modules.
+use std::time::Duration;
+use bytes::Bytes;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use crate::error::Error;
+
+const GREETING_SIZE: usize = 64;
+const SIGNATURE_START: u8 = 0xFF;
+const SIGNATURE_END: u8 = 0x7F;
+const VERSION_MAJOR: u8 = 3;
+const VERSION_MINOR: u8 = 0;
+const MECHANISM: &[u8] = b"NULL";
+const MECHANISM_SIZE: usize = 20;
+const FLAG_MORE: u8 = 0b001;
+const FLAG_LONG: u8 = 0b010;
+const FLAG_COMMAND: u8 = 0b100;
+/// Larger frames are rejected.
+const MAX_FRAME_SIZE: u64 = 256 * 1024 * 1024;
+/// Initial capacity of a frame body, grown as the body is received rather than trusting the declared size.
+const FRAME_CHUNK_SIZE: usize = 64 * 1024;
+const READY_COMMAND: &[u8] = b"READY";
+const SOCKET_TYPE_PROPERTY: &str = "Socket-Type";
+/// First byte of the messages sent by subscribers to subscribe to the topic prefix that follows.
+pub(crate) const SUBSCRIBE: u8 = 1;
+/// First byte of the messages sent by subscribers to unsubscribe from the topic prefix that follows.
+pub(crate) const UNSUBSCRIBE: u8 = 0;
+
+/// A multipart message: the first frame is the topic.
+pub(crate) type Frames = Vec<Bytes>;
+
+fn protocol_error(message: &str) -> Error {
+    Error::ProtocolError(message.to_string())
+}
+
+fn greeting() -> [u8; GREETING_SIZE] {
+    let mut greeting = [0; GREETING_SIZE];
+    greeting[0] = SIGNATURE_START;
+    greeting[9] = SIGNATURE_END;
+    greeting[10] = VERSION_MAJOR;
+    greeting[11] = VERSION_MINOR;
+    greeting[12..12 + MECHANISM.len()].copy_from_slice(MECHANISM);
+    greeting
+}
+
+fn ready_command(socket_type: &str) -> Vec<u8> {
+    let mut body = vec![u8::try_from(READY_COMMAND.len()).unwrap_or_default()];
+    body.extend_from_slice(READY_COMMAND);
+    body.push(u8::try_from(SOCKET_TYPE_PROPERTY.len()).unwrap_or_default());
+    body.extend_from_slice(SOCKET_TYPE_PROPERTY.as_bytes());
+    body.extend_from_slice(&u32::try_from(socket_type.len()).unwrap_or_default().to_be_bytes());
+    body.extend_from_slice(socket_type.as_bytes());
+    body
+}
+
+fn parse_ready_command(body: &[u8]) -> Result<String, Error> {
+    let (name, mut properties) = body.split_first()
+        .and_then(|(size, rest)| (rest.len() >= usize::from(*size)).then(|| rest.split_at(usize::from(*size))))
+        .ok_or_else(|| protocol_error("truncated command"))?;
+    if name != READY_COMMAND {
+        return Err(protocol_error("READY command expected"));
+    }
+    while let Some((size, rest)) = properties.split_first() {
+        let size = usize::from(*size);
+        if rest.len() < size + 4 { return Err(protocol_error("truncated property")); }
+        let (property, rest) = rest.split_at(size);
+        let (value_size, rest) = rest.split_at(4);
+        let value_size = usize::try_from(u32::from_be_bytes([value_size[0], value_size[1], value_size[2], value_size[3]]))
+            .map_err(|_| protocol_error("property too long"))?;
+        if rest.len() < value_size { return Err(protocol_error("truncated property value")); }
+        let (value, rest) = rest.split_at(value_size);
+        if property.eq_ignore_ascii_case(SOCKET_TYPE_PROPERTY.as_bytes()) {
+            return String::from_utf8(value.to_vec()).map_err(|_| protocol_error("invalid socket type"));
+        }
+        properties = rest;
+    }
+    Err(protocol_error("socket type not found"))
+}
+
+/// Exchanges greeting and READY command with a peer, announcing `socket_type`.
+/// Returns the socket type of the peer, failing if it does not complete the handshake within `timeout`.
+pub(crate) async fn handshake<S: AsyncRead + AsyncWrite + Unpin>(stream: &mut S, socket_type: &str, timeout: Duration) -> Result<String, Error> {
+    tokio::time::timeout(timeout, exchange_ready(stream, socket_type)).await
+        .map_err(|_| protocol_error("handshake timed out"))?
+}
+
+async fn exchange_ready<S: AsyncRead + AsyncWrite + Unpin>(stream: &mut S, socket_type: &str) -> Result<String, Error> {
+    stream.write_all(&greeting()).await?;
+    stream.flush().await?;
+    let mut peer_greeting = [0; GREETING_SIZE];
+    stream.read_exact(&mut peer_greeting).await?;
+    if peer_greeting[0] != SIGNATURE_START || peer_greeting[9] != SIGNATURE_END {
+        return Err(protocol_error("invalid greeting"));
+    }
+    if peer_greeting[10] < VERSION_MAJOR {
+        return Err(Error::ProtocolError(format!("unsupported ZMTP version {}", peer_greeting[10])));
+    }
+    let mechanism = &peer_greeting[12..12 + MECHANISM_SIZE];
+    if !mechanism.starts_with(MECHANISM) || mechanism[MECHANISM.len()..].iter().any(|byte| *byte != 0) {
+        return Err(protocol_error("only the NULL security mechanism is supported"));
+    }
+    write_frame(stream, &ready_command(socket_type), FLAG_COMMAND).await?;
+    stream.flush().await?;
+    // the peer is not known yet: only a short frame is accepted for its READY command
+    let (flags, body) = read_frame(stream, false).await?.ok_or_else(|| protocol_error("connection closed during handshake"))?;
+    if flags & FLAG_COMMAND == 0 {
+        return Err(protocol_error("READY command expected"));
+    }
+    parse_ready_command(&body)
+}
+
+/// Reads the next frame; long frames (more than 255 bytes) are rejected unless `long_frames` is set.
+async fn read_frame<R: AsyncRead + Unpin>(reader: &mut R, long_frames: bool) -> Result<Option<(u8, Bytes)>, Error> {
+    let flags = match reader.read_u8().await {
+        Ok(flags) => flags,
+        Err(error) if error.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(error) => return Err(error.into())
+    };
+    let size = if flags & FLAG_LONG == 0 {
+        u64::from(reader.read_u8().await?)
+    } else {
+        if !long_frames { return Err(protocol_error("long frame not allowed")); }
+        let size = reader.read_u64().await?;
+        if size > MAX_FRAME_SIZE { return Err(protocol_error("frame too long")); }
+        size
+    };
+    let mut body = Vec::with_capacity(usize::try_from(size).unwrap_or(FRAME_CHUNK_SIZE).min(FRAME_CHUNK_SIZE));
+    let read = (&mut *reader).take(size).read_to_end(&mut body).await?;
+    if (read as u64) < size {
+        return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+    }
+    Ok(Some((flags, Bytes::from(body))))
+}
+
+async fn write_frame<W: AsyncWrite + Unpin>(writer: &mut W, body: &[u8], flags: u8) -> Result<(), Error> {
+    if let Ok(size) = u8::try_from(body.len()) {
+        writer.write_all(&[flags, size]).await?;
+    } else {
+        writer.write_u8(flags | FLAG_LONG).await?;
+        writer.write_u64(body.len() as u64).await?;
+    }
+    writer.write_all(body).await?;
+    Ok(())
+}
+
+/// Reads the next message, skipping commands. Returns `None` when the peer disconnects.
+pub(crate) async fn read_message<R: AsyncRead + Unpin>(reader: &mut R) -> Result<Option<Frames>, Error> {
+    let mut frames = Frames::new();
+    while let Some((flags, body)) = read_frame(reader, true).await? {
+        if flags & FLAG_COMMAND != 0 { continue; }
+        frames.push(body);
+        if flags & FLAG_MORE == 0 { return Ok(Some(frames)); }
+    }
+    Ok(None)
+}
+
+pub(crate) async fn write_message<W: AsyncWrite + Unpin>(writer: &mut W, frames: &[Bytes]) -> Result<(), Error> {
+    let last = frames.len().saturating_sub(1);
+    for (index, frame) in frames.iter().enumerate() {
+        write_frame(writer, frame, if index == last { 0 } else { FLAG_MORE }).await?;
+    }
+    writer.flush().await?;
+    Ok(())
+}
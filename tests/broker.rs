@@ -0,0 +1,418 @@
+use std::time::Duration;
+use bytes::Bytes;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::task::JoinHandle;
+use zeromq::{PubSocket, Socket, SocketSend, ZmqMessage};
+use alfred_core::broker::{Broker, UNROUTED_TOPIC};
+use alfred_core::config::{Config, UnroutedPolicy};
+use alfred_core::dead_letter::{DEAD_LETTER_TOPIC, TOPIC_PARAM};
+use alfred_core::connection::{Connection, ConnectionStatus, MODULE_INFO_TOPIC_REQUEST, MODULE_INFO_TOPIC_RESPONSE, MODULE_OFFLINE_TOPIC, MODULE_ONLINE_TOPIC};
+use alfred_core::error::Error;
+use alfred_core::message::Message;
+use common::{broker, ephemeral_config, spawn_broker, text, TIMEOUT};
+
+mod common;
+
+#[tokio::test]
+async fn messages_are_routed_by_topic_prefix() {
+    let config = broker().await;
+    let events = Connection::new(&config).await.expect("connection");
+    events.listen("event.").await.expect("listen");
+    let publisher = Connection::new(&config).await.expect("connection");
+    publisher.send("other", &text("ignored")).await.expect("send");
+    publisher.send("event.test", &text("routed")).await.expect("send");
+    let (topic, message) = tokio::time::timeout(TIMEOUT, events.receive_all()).await
+        .expect("message should be routed")
+        .expect("receive");
+    assert_eq!(topic, "event.test");
+    assert_eq!(message.text, "routed");
+}
+
+#[tokio::test]
+async fn unsubscribed_topics_are_not_routed() {
+    let config = broker().await;
+    let subscriber = Connection::new(&config).await.expect("connection");
+    subscriber.listen("first").await.expect("listen");
+    subscriber.listen("second").await.expect("listen");
+    subscriber.unlisten("first").await.expect("unlisten");
+    // subscriptions are processed in order: once "sync" is routed, "first" is unsubscribed
+    subscriber.listen("sync").await.expect("listen");
+    let publisher = Connection::new(&config).await.expect("connection");
+    publisher.send("sync", &text("sync")).await.expect("send");
+    let (topic, _) = tokio::time::timeout(TIMEOUT, subscriber.receive_all()).await
+        .expect("message should be routed")
+        .expect("receive");
+    assert_eq!(topic, "sync");
+    publisher.send("first", &text("ignored")).await.expect("send");
+    publisher.send("second", &text("routed")).await.expect("send");
+    let (topic, _) = tokio::time::timeout(TIMEOUT, subscriber.receive_all()).await
+        .expect("message should be routed")
+        .expect("receive");
+    assert_eq!(topic, "second");
+}
+
+#[tokio::test]
+async fn large_messages_are_routed_to_every_subscriber() {
+    let config = broker().await;
+    let first = Connection::new(&config).await.expect("connection");
+    first.listen("data").await.expect("listen");
+    let second = Connection::new(&config).await.expect("connection");
+    second.listen("data").await.expect("listen");
+    let message = Message { data: vec![42; 1_000_000].into(), ..Message::default() };
+    let publisher = Connection::new(&config).await.expect("connection");
+    publisher.send("data", &message).await.expect("send");
+    for subscriber in [first, second] {
+        let (_, received) = tokio::time::timeout(TIMEOUT, subscriber.receive_all()).await
+            .expect("message should be routed")
+            .expect("receive");
+        assert_eq!(received.data, message.data);
+    }
+}
+
+#[tokio::test]
+async fn stopped_brokers_close_every_connection() {
+    let mut config = ephemeral_config();
+    let broker = Broker::bind(&config).await.expect("broker should bind ephemeral ports");
+    config.alfred.pub_url = Some(broker.pub_url());
+    config.alfred.sub_url = Some(broker.sub_url());
+    let handle = broker.spawn();
+    let subscriber = Connection::new(&config).await.expect("connection");
+    subscriber.listen("stop").await.expect("listen");
+    let publisher = Connection::new(&config).await.expect("connection");
+    handle.stop().await.expect("broker should stop");
+    let _ = publisher.send("stop", &text("lost")).await;
+    assert!(tokio::time::timeout(Duration::from_millis(200), subscriber.receive_all()).await.is_err());
+    // the endpoints are free again
+    config.alfred.pub_bind = vec![config.get_alfred_pub_url()];
+    config.alfred.sub_bind = vec![config.get_alfred_sub_url()];
+    assert!(Broker::bind(&config).await.is_ok());
+}
+
+#[tokio::test]
+async fn connections_are_restored_after_a_broker_restart() {
+    let mut config = ephemeral_config();
+    config.alfred.probe_interval = 50;
+    config.alfred.reconnect_delay = 20;
+    config.alfred.reconnect_max_delay = 100;
+    let broker = Broker::bind(&config).await.expect("broker should bind ephemeral ports");
+    config.alfred.pub_url = Some(broker.pub_url());
+    config.alfred.sub_url = Some(broker.sub_url());
+    config.alfred.pub_bind = vec![broker.pub_url()];
+    config.alfred.sub_bind = vec![broker.sub_url()];
+    let handle = broker.spawn();
+    let subscriber = Connection::new(&config).await.expect("connection");
+    subscriber.listen("restart").await.expect("listen");
+    let mut status = subscriber.status();
+
+    handle.stop().await.expect("broker should stop");
+    tokio::time::timeout(TIMEOUT, status.wait_for(|status| *status == ConnectionStatus::Disconnected)).await
+        .expect("broker loss should be detected")
+        .expect("status");
+    Broker::bind(&config).await.expect("broker should bind the same endpoints").spawn();
+    tokio::time::timeout(TIMEOUT, status.wait_for(|status| *status == ConnectionStatus::Connected)).await
+        .expect("connection should be restored")
+        .expect("status");
+
+    let publisher = Connection::new(&config).await.expect("connection");
+    publisher.send("restart", &text("restored")).await.expect("send");
+    let (topic, message) = tokio::time::timeout(TIMEOUT, subscriber.receive_all()).await
+        .expect("subscription should be replayed")
+        .expect("receive");
+    assert_eq!((topic.as_str(), message.text.as_str()), ("restart", "restored"));
+}
+
+#[tokio::test]
+async fn reconnection_stops_when_the_connection_is_dropped() {
+    let mut config = ephemeral_config();
+    config.alfred.probe_interval = 50;
+    config.alfred.reconnect_delay = 20;
+    config.alfred.reconnect_max_delay = 100;
+    let broker = Broker::bind(&config).await.expect("broker should bind ephemeral ports");
+    config.alfred.pub_url = Some(broker.pub_url());
+    config.alfred.sub_url = Some(broker.sub_url());
+    let handle = broker.spawn();
+    let connection = Connection::new(&config).await.expect("connection");
+    let mut status = connection.status();
+
+    handle.stop().await.expect("broker should stop");
+    tokio::time::timeout(TIMEOUT, status.wait_for(|status| *status == ConnectionStatus::Disconnected)).await
+        .expect("broker loss should be detected")
+        .expect("status");
+    drop(connection);
+    // the status is closed once the supervisor stops
+    let closed = tokio::time::timeout(TIMEOUT, status.changed()).await.expect("supervisor should stop");
+    assert!(closed.is_err());
+}
+
+fn socket_dir() -> std::path::PathBuf {
+    let socket_dir = std::env::temp_dir().join(format!("alfred-broker-{}", Message::new_id()));
+    std::fs::create_dir_all(&socket_dir).expect("socket directory");
+    socket_dir
+}
+
+#[tokio::test]
+async fn messages_are_routed_over_ipc() {
+    let socket_dir = socket_dir();
+    let mut config = Config::default();
+    config.alfred.pub_url = Some(format!("ipc://{}", socket_dir.join("pub.sock").display()));
+    config.alfred.sub_url = Some(format!("ipc://{}", socket_dir.join("sub.sock").display()));
+    let config = spawn_broker(config).await;
+    assert!(socket_dir.join("pub.sock").exists());
+    let subscriber = Connection::new(&config).await.expect("connection");
+    subscriber.listen("ipc").await.expect("listen");
+    let publisher = Connection::new(&config).await.expect("connection");
+    publisher.send("ipc", &text("routed")).await.expect("send");
+    let (_, message) = tokio::time::timeout(TIMEOUT, subscriber.receive_all()).await
+        .expect("message should be routed")
+        .expect("receive");
+    assert_eq!(message.text, "routed");
+    std::fs::remove_dir_all(&socket_dir).expect("socket directory cleanup");
+}
+
+#[tokio::test]
+async fn every_bind_endpoint_is_served() {
+    let socket_dir = socket_dir();
+    let ipc_pub_url = format!("ipc://{}", socket_dir.join("pub.sock").display());
+    let mut config = Config::default();
+    config.alfred.pub_bind = vec!["tcp://127.0.0.1:0".to_string(), ipc_pub_url.clone()];
+    config.alfred.sub_bind = vec!["tcp://127.0.0.1:0".to_string()];
+    let broker = Broker::bind(&config).await.expect("broker should bind every endpoint");
+    let pub_urls = broker.pub_urls();
+    assert_eq!(pub_urls.len(), 2);
+    assert_eq!(pub_urls[1], ipc_pub_url);
+    config.alfred.pub_url = Some(broker.pub_url());
+    config.alfred.sub_url = Some(broker.sub_url());
+    broker.spawn();
+
+    let subscriber = Connection::new(&config).await.expect("connection");
+    subscriber.listen("endpoint").await.expect("listen");
+    for pub_url in pub_urls {
+        config.alfred.pub_url = Some(pub_url.clone());
+        let publisher = Connection::new(&config).await.expect("connection");
+        publisher.send("endpoint", &text(&pub_url)).await.expect("send");
+        let (_, message) = tokio::time::timeout(TIMEOUT, subscriber.receive_all()).await
+            .expect("message should be routed")
+            .expect("receive");
+        assert_eq!(message.text, pub_url);
+    }
+    std::fs::remove_dir_all(&socket_dir).expect("socket directory cleanup");
+}
+
+#[tokio::test]
+async fn bind_errors_name_the_endpoint() {
+    let mut config = Config::default();
+    config.alfred.pub_bind = vec!["udp://127.0.0.1:0".to_string()];
+    assert!(matches!(Broker::bind(&config).await, Err(Error::EndpointError(endpoint)) if endpoint == "udp://127.0.0.1:0"));
+
+    let used = tokio::net::TcpListener::bind("127.0.0.1:0").await.expect("listener");
+    let used_url = format!("tcp://{}", used.local_addr().expect("address"));
+    config.alfred.pub_bind = vec![used_url.clone()];
+    assert!(matches!(Broker::bind(&config).await, Err(Error::BindError(endpoint, _)) if endpoint == used_url));
+}
+
+#[tokio::test]
+async fn only_stale_ipc_sockets_are_replaced() {
+    let socket_dir = socket_dir();
+    let mut config = Config::default();
+    config.alfred.sub_bind = vec!["tcp://127.0.0.1:0".to_string()];
+
+    let file = socket_dir.join("file.sock");
+    std::fs::write(&file, "not a socket").expect("file");
+    config.alfred.pub_bind = vec![format!("ipc://{}", file.display())];
+    assert!(matches!(Broker::bind(&config).await, Err(Error::BindError(..))));
+    assert_eq!(std::fs::read_to_string(&file).expect("file should be kept"), "not a socket");
+
+    let live = socket_dir.join("live.sock");
+    let _listener = tokio::net::UnixListener::bind(&live).expect("listener");
+    config.alfred.pub_bind = vec![format!("ipc://{}", live.display())];
+    assert!(matches!(Broker::bind(&config).await, Err(Error::BindError(..))));
+
+    let stale = socket_dir.join("stale.sock");
+    drop(std::os::unix::net::UnixListener::bind(&stale).expect("listener"));
+    config.alfred.pub_bind = vec![format!("ipc://{}", stale.display())];
+    assert!(Broker::bind(&config).await.is_ok(), "stale socket should be replaced");
+    std::fs::remove_dir_all(&socket_dir).expect("socket directory cleanup");
+}
+
+#[tokio::test]
+async fn subscriptions_are_listed_by_the_broker() {
+    let config = broker().await;
+    let connection = Connection::new(&config).await.expect("connection");
+    connection.listen("event.").await.expect("listen");
+    // subscriptions are processed in order: "event." is known once the reply topic is
+    let subscriptions = connection.broker_subscriptions(TIMEOUT).await.expect("subscriptions");
+    assert_eq!(subscriptions.get("event."), Some(&1));
+    assert_eq!(subscriptions.get(MODULE_INFO_TOPIC_REQUEST), Some(&1));
+}
+
+#[tokio::test]
+async fn unrouted_messages_are_published_when_enabled() {
+    let mut config = ephemeral_config();
+    config.alfred.unrouted = UnroutedPolicy::Publish;
+    let config = spawn_broker(config).await;
+    let watcher = Connection::new(&config).await.expect("connection");
+    watcher.listen(UNROUTED_TOPIC).await.expect("listen");
+    let publisher = Connection::new(&config).await.expect("connection");
+    for topic in ["nobody", "probes"] {
+        publisher.send(topic, &text("lost")).await.expect("send");
+        let (warning_topic, warning) = tokio::time::timeout(TIMEOUT, watcher.receive_all()).await
+            .expect("warning should be published")
+            .expect("receive");
+        assert_eq!(warning_topic, UNROUTED_TOPIC);
+        assert_eq!(warning.params.get("topic").map(String::as_str), Some(topic));
+    }
+}
+
+#[tokio::test]
+async fn wildcard_subscribers_do_not_route_messages() {
+    let mut config = ephemeral_config();
+    config.alfred.unrouted = UnroutedPolicy::Publish;
+    let config = spawn_broker(config).await;
+    let logs = Connection::new(&config).await.expect("connection");
+    logs.listen("").await.expect("listen");
+    let watcher = Connection::new(&config).await.expect("connection");
+    watcher.listen(UNROUTED_TOPIC).await.expect("listen");
+    let publisher = Connection::new(&config).await.expect("connection");
+    publisher.send("nobody", &text("lost")).await.expect("send");
+    let (warning_topic, warning) = tokio::time::timeout(TIMEOUT, watcher.receive_all()).await
+        .expect("warning should be published")
+        .expect("receive");
+    assert_eq!(warning_topic, UNROUTED_TOPIC);
+    assert_eq!(warning.params.get("topic").map(String::as_str), Some("nobody"));
+    // still delivered to the wildcard subscriber
+    tokio::time::timeout(TIMEOUT, async {
+        while logs.receive_all().await.expect("receive").0 != "nobody" {}
+    }).await.expect("message should be delivered to the wildcard subscriber");
+}
+
+async fn announce(config: &Config, module_name: &str) -> Connection {
+    let module = Connection::new(config).await.expect("connection");
+    module.send(MODULE_INFO_TOPIC_RESPONSE, &text(module_name)).await.expect("send");
+    module
+}
+
+async fn presence(watcher: &Connection) -> (String, Message) {
+    tokio::time::timeout(TIMEOUT, watcher.receive_all()).await
+        .expect("presence event should be published")
+        .expect("receive")
+}
+
+#[tokio::test]
+async fn modules_are_offline_when_disconnected() {
+    let config = broker().await;
+    let watcher = Connection::new(&config).await.expect("connection");
+    watcher.listen(MODULE_ONLINE_TOPIC).await.expect("listen");
+    watcher.listen(MODULE_OFFLINE_TOPIC).await.expect("listen");
+    let module = announce(&config, "test").await;
+    let (topic, event) = presence(&watcher).await;
+    assert_eq!((topic.as_str(), event.text.as_str()), (MODULE_ONLINE_TOPIC, "test"));
+    drop(module);
+    let (topic, event) = presence(&watcher).await;
+    assert_eq!((topic.as_str(), event.text.as_str()), (MODULE_OFFLINE_TOPIC, "test"));
+    assert_eq!(event.params.get("reason").map(String::as_str), Some("disconnected"));
+}
+
+#[tokio::test]
+async fn modules_are_offline_without_heartbeat() {
+    let mut config = ephemeral_config();
+    config.alfred.heartbeat_timeout = 200;
+    let config = spawn_broker(config).await;
+    let watcher = Connection::new(&config).await.expect("connection");
+    watcher.listen(MODULE_OFFLINE_TOPIC).await.expect("listen");
+    let _module = announce(&config, "test").await;
+    let (topic, event) = presence(&watcher).await;
+    assert_eq!((topic.as_str(), event.text.as_str()), (MODULE_OFFLINE_TOPIC, "test"));
+    assert_eq!(event.params.get("reason").map(String::as_str), Some("timeout"));
+}
+
+/// Publishes raw frames until the task is aborted, as a raw socket may connect after the first sends.
+fn publish_raw(config: &Config, frames: Vec<Bytes>) -> JoinHandle<()> {
+    let pub_url = config.get_alfred_pub_url();
+    tokio::spawn(async move {
+        let mut socket = PubSocket::new();
+        socket.connect(&pub_url).await.expect("connect");
+        loop {
+            let message = ZmqMessage::try_from(frames.clone()).expect("frames");
+            socket.send(message).await.expect("send");
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+}
+
+#[tokio::test]
+async fn malformed_messages_are_skipped_and_dead_lettered() {
+    let config = broker().await;
+    let watcher = Connection::new(&config).await.expect("connection");
+    watcher.listen(DEAD_LETTER_TOPIC).await.expect("listen");
+    let module = Connection::new(&config).await.expect("connection");
+    module.listen("events").await.expect("listen");
+    let garbage = Bytes::from_static(&[0xFF, 0x00, 0x42]);
+    let raw = publish_raw(&config, vec![Bytes::from_static(b"events"), garbage.clone()]);
+    let receiving = tokio::spawn(async move {
+        let received = module.receive_all().await;
+        (received.map(|(topic, _)| topic), module.malformed_count())
+    });
+    let (topic, dead_letter) = tokio::time::timeout(TIMEOUT, watcher.receive_all()).await
+        .expect("dead letter should be published")
+        .expect("receive");
+    raw.abort();
+    assert_eq!(topic, DEAD_LETTER_TOPIC);
+    assert_eq!(dead_letter.params.get(TOPIC_PARAM).map(String::as_str), Some("events"));
+    assert_eq!(dead_letter.data, garbage);
+    let publisher = Connection::new(&config).await.expect("connection");
+    publisher.send("events", &text("valid")).await.expect("send");
+    let (topic, malformed) = tokio::time::timeout(TIMEOUT, receiving).await
+        .expect("valid message should be received")
+        .expect("task");
+    assert_eq!(topic.expect("receive"), "events");
+    assert!(malformed > 0);
+}
+
+#[tokio::test]
+async fn malformed_messages_fail_in_strict_mode() {
+    let mut config = broker().await;
+    config.alfred.strict_receive = true;
+    let module = Connection::new(&config).await.expect("connection");
+    module.listen("events").await.expect("listen");
+    let raw = publish_raw(&config, vec![Bytes::from_static(b"events"), Bytes::from_static(&[0xFF])]);
+    let received = tokio::time::timeout(TIMEOUT, module.receive_all()).await.expect("error should be returned");
+    raw.abort();
+    assert!(matches!(received, Err(Error::MalformedMessage { .. })));
+}
+
+#[tokio::test]
+async fn silent_connections_are_closed() {
+    let mut config = ephemeral_config();
+    config.alfred.handshake_timeout = 100;
+    let config = spawn_broker(config).await;
+    let address = config.alfred.pub_url.as_deref().and_then(|url| url.strip_prefix("tcp://")).expect("tcp endpoint");
+    let mut stream = tokio::net::TcpStream::connect(address).await.expect("connect");
+    let mut greeting = Vec::new();
+    tokio::time::timeout(TIMEOUT, stream.read_to_end(&mut greeting)).await
+        .expect("connection should be closed by the broker")
+        .expect("read");
+    assert!(!greeting.is_empty());
+}
+
+#[tokio::test]
+async fn long_frames_are_rejected_during_the_handshake() {
+    let mut config = ephemeral_config();
+    config.alfred.handshake_timeout = 60_000;
+    let config = spawn_broker(config).await;
+    let address = config.alfred.pub_url.as_deref().and_then(|url| url.strip_prefix("tcp://")).expect("tcp endpoint");
+    let mut stream = tokio::net::TcpStream::connect(address).await.expect("connect");
+    let mut greeting = [0; 64];
+    greeting[0] = 0xFF;
+    greeting[9] = 0x7F;
+    greeting[10] = 3;
+    greeting[12..16].copy_from_slice(b"NULL");
+    stream.write_all(&greeting).await.expect("greeting");
+    // a long READY command announcing 200 MiB
+    stream.write_all(&[0b110]).await.expect("flags");
+    stream.write_u64(200 * 1024 * 1024).await.expect("size");
+    let mut received = Vec::new();
+    let closed = tokio::time::timeout(TIMEOUT, stream.read_to_end(&mut received)).await
+        .expect("connection should be closed by the broker");
+    // the size left unread may reset the connection
+    assert!(closed.map_or_else(|e| e.kind() == std::io::ErrorKind::ConnectionReset, |_| !received.is_empty()));
+}
@@ -0,0 +1,47 @@
+//! Fixtures shared by the integration tests, each test crate using a part of them.
+#![allow(dead_code)]
+
+use std::sync::Arc;
+use std::time::Duration;
+use alfred_core::{AlfredModule, ModuleDetailsBuilder};
+use alfred_core::broker::Broker;
+use alfred_core::config::Config;
+use alfred_core::memory::MemoryBus;
+use alfred_core::message::Message;
+
+pub const TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Default config, with the broker ports picked by the system.
+pub fn ephemeral_config() -> Config {
+    let mut config = Config::default();
+    config.alfred.pub_port = 0;
+    config.alfred.sub_port = 0;
+    config
+}
+
+/// Spawns a broker on ephemeral ports, returning the config to connect to it.
+pub async fn broker() -> Config {
+    spawn_broker(ephemeral_config()).await
+}
+
+pub async fn spawn_broker(mut config: Config) -> Config {
+    let broker = Broker::bind(&config).await.expect("broker should bind ephemeral ports");
+    config.alfred.pub_url = Some(broker.pub_url());
+    config.alfred.sub_url = Some(broker.sub_url());
+    broker.spawn();
+    config
+}
+
+/// Starts a module on the memory bus.
+pub async fn module(bus: &MemoryBus, module_name: &'static str) -> AlfredModule {
+    let details = ModuleDetailsBuilder::new()
+        .module_name(module_name)
+        .version("1.0.0")
+        .transport(Arc::new(bus.clone()))
+        .build();
+    AlfredModule::new_with_details(details).await.expect("module should start on the memory bus")
+}
+
+pub fn text(text: &str) -> Message {
+    Message { text: text.to_string(), ..Message::default() }
+}
@@ -0,0 +1,29 @@
+use alfred_core::endpoint::Endpoint;
+
+fn tcp(host: &str, port: u16) -> Endpoint {
+    Endpoint::Tcp { host: host.to_string(), port }
+}
+
+#[test]
+fn ipv6_hosts_are_parsed_without_brackets() {
+    let endpoint: Endpoint = "tcp://[::1]:5678".parse().expect("endpoint");
+    assert_eq!(endpoint, tcp("::1", 5678));
+    assert_eq!(endpoint.to_string(), "tcp://[::1]:5678");
+    assert_eq!(endpoint.connect_url(), "tcp://[::1]:5678");
+    let scoped: Endpoint = "tcp://[fe80::1%eth0]:5678".parse().expect("endpoint");
+    assert_eq!(scoped, tcp("fe80::1%eth0", 5678));
+    for invalid in ["tcp://[::1:5678", "tcp://[]:5678", "tcp://[::1]"] {
+        assert!(invalid.parse::<Endpoint>().is_err(), "{invalid} should not parse");
+    }
+}
+
+#[test]
+fn any_address_is_connected_through_localhost() {
+    let any_v6: Endpoint = "tcp://[::]:5678".parse().expect("endpoint");
+    assert_eq!(any_v6, tcp("::", 5678));
+    assert_eq!(any_v6.connect_url(), "tcp://[::1]:5678");
+    for any in ["tcp://*:5678", "tcp://0.0.0.0:5678"] {
+        assert_eq!(any.parse::<Endpoint>().expect("endpoint").connect_url(), "tcp://127.0.0.1:5678");
+    }
+    assert_eq!(tcp("192.168.1.2", 5678).connect_url(), "tcp://192.168.1.2:5678");
+}
@@ -0,0 +1,361 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+use std::time::Duration;
+use alfred_core::{AlfredModule, ModuleDetailsBuilder};
+use alfred_core::config::Config;
+use alfred_core::connection::{Connection, ACK_TOPIC_PARAM, ACK_TOPIC_PREFIX, MODULE_HEARTBEAT_TOPIC, MODULE_INFO_TOPIC_RESPONSE, REPLY_TOPIC_PREFIX};
+use alfred_core::dead_letter::{DeadLetter, DEAD_LETTER_TOPIC};
+use alfred_core::durable::{DurableSubscription, DURABLE_SUBSCRIBE_TOPIC};
+use alfred_core::error::Error;
+use alfred_core::memory::MemoryBus;
+use alfred_core::message::Message;
+use alfred_core::module_info::ModuleInfo;
+use common::{module, text, TIMEOUT};
+
+mod common;
+
+#[tokio::test]
+async fn module_info_is_published_on_start() {
+    let bus = MemoryBus::new();
+    let _module = module(&bus, "test").await;
+    let (topic, message) = bus.next_published().await.expect("bus is open");
+    assert_eq!(topic, MODULE_INFO_TOPIC_RESPONSE);
+    let info = ModuleInfo::from_message(&message).expect("module info");
+    assert_eq!(info.name, "test");
+    assert_eq!(info.version, "1.0.0");
+}
+
+#[tokio::test]
+async fn module_info_lists_every_topic() {
+    let bus = MemoryBus::new();
+    let mut module = module(&bus, "test").await;
+    module.listen("first").await.expect("listen");
+    module.listen("second,with,commas").await.expect("listen");
+    tokio::spawn(async move { module.run().await });
+    let client = Connection::with_transport(&Config::default(), Arc::new(bus.clone())).await.expect("connection");
+    let modules = client.discover_modules(Duration::from_millis(100)).await.expect("discover");
+    assert_eq!(modules.len(), 1);
+    assert_eq!(modules[0].topics, BTreeSet::from(["first".to_string(), "second,with,commas".to_string()]));
+}
+
+#[tokio::test]
+async fn injected_messages_are_received_by_prefix() {
+    let bus = MemoryBus::new();
+    let mut module = module(&bus, "test").await;
+    module.listen("event.test").await.expect("listen");
+    bus.inject("other.topic", &Message { text: "ignored".to_string(), ..Message::default() });
+    bus.inject("event.test.created", &Message { text: "received".to_string(), ..Message::default() });
+    let (topic, message) = tokio::time::timeout(TIMEOUT, module.receive()).await
+        .expect("message should be received")
+        .expect("receive");
+    assert_eq!(topic, "event.test.created");
+    assert_eq!(message.text, "received");
+}
+
+#[tokio::test]
+async fn topics_starting_with_probe_are_received() {
+    let bus = MemoryBus::new();
+    let client = Connection::with_transport(&Config::default(), Arc::new(bus.clone())).await.expect("connection");
+    client.listen("probe").await.expect("listen");
+    bus.inject("probes", &text("results"));
+    let (topic, message) = tokio::time::timeout(TIMEOUT, client.receive_all()).await
+        .expect("message should be received")
+        .expect("receive");
+    assert_eq!((topic.as_str(), message.text.as_str()), ("probes", "results"));
+}
+
+#[tokio::test]
+async fn application_topics_like_private_ones_are_received() {
+    let bus = MemoryBus::new();
+    let client = Connection::with_transport(&Config::default(), Arc::new(bus.clone())).await.expect("connection");
+    let topics = ["reply.status", "ack.order", "probe.results"];
+    for topic in topics {
+        client.listen(topic).await.expect("listen");
+        bus.inject(topic, &text(topic));
+    }
+    for expected in topics {
+        let (topic, _) = tokio::time::timeout(TIMEOUT, client.receive_all()).await
+            .expect("message should be received")
+            .expect("receive");
+        assert_eq!(topic, expected);
+    }
+}
+
+#[tokio::test]
+async fn topics_starting_with_probe_are_recorded() {
+    let bus = MemoryBus::new();
+    let client = Connection::with_transport(&Config::default(), Arc::new(bus.clone())).await.expect("connection");
+    client.send("probes", &text("results")).await.expect("send");
+    let (topic, _) = tokio::time::timeout(TIMEOUT, bus.next_published()).await
+        .expect("message should be recorded")
+        .expect("bus is open");
+    assert_eq!(topic, "probes");
+}
+
+#[tokio::test]
+async fn request_between_modules() {
+    let bus = MemoryBus::new();
+    let mut server = module(&bus, "server").await;
+    server.on("upper", |_, message: Message| async move {
+        Ok(Some(Message { text: message.text.to_uppercase(), ..Message::default() }))
+    }).await.expect("on");
+    tokio::spawn(async move { server.run().await });
+    let client = module(&bus, "client").await;
+    let request = Message { text: "hello".to_string(), ..Message::default() };
+    let reply = client.request("upper", &request, TIMEOUT).await.expect("reply");
+    assert_eq!(reply.text, "HELLO");
+    assert!(!reply.correlation_id.is_empty());
+}
+
+#[tokio::test]
+async fn late_replies_are_dropped() {
+    let bus = MemoryBus::new();
+    let mut server = module(&bus, "server").await;
+    server.on("slow", |_, message: Message| async move {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        Ok(Some(message))
+    }).await.expect("on");
+    tokio::spawn(async move { server.run().await });
+    let client = Connection::with_transport(&Config::default(), Arc::new(bus.clone())).await.expect("connection");
+    client.listen(REPLY_TOPIC_PREFIX).await.expect("listen");
+    client.listen("next").await.expect("listen");
+    let result = client.request("slow", &text("late"), Duration::from_millis(10)).await;
+    assert!(matches!(result, Err(Error::RequestTimeout(_))));
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    bus.inject("next", &text("next"));
+    let (topic, _) = tokio::time::timeout(TIMEOUT, client.receive_all()).await
+        .expect("message should be received")
+        .expect("receive");
+    assert_eq!(topic, "next");
+}
+
+#[tokio::test]
+async fn sequential_run_keeps_the_order() {
+    let bus = MemoryBus::new();
+    let mut router = module(&bus, "router").await;
+    let connection = router.connection.clone();
+    router.on("in", move |_, message: Message| {
+        let connection = connection.clone();
+        async move {
+            if message.text == "slow" {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+            connection.send("out", &message).await?;
+            Ok(None)
+        }
+    }).await.expect("on");
+    tokio::spawn(async move { router.run_sequential().await });
+    bus.next_published().await.expect("bus is open");
+    bus.inject("in", &text("slow"));
+    bus.inject("in", &text("fast"));
+    let mut forwarded = Vec::new();
+    while forwarded.len() < 2 {
+        let (topic, message) = tokio::time::timeout(TIMEOUT, bus.next_published()).await
+            .expect("messages should be forwarded")
+            .expect("bus is open");
+        if topic == "out" { forwarded.push(message.text); }
+    }
+    assert_eq!(forwarded, ["slow", "fast"]);
+}
+
+#[tokio::test]
+async fn heartbeats_are_published_periodically() {
+    let bus = MemoryBus::new();
+    let mut config = Config::default();
+    config.alfred.heartbeat_interval = 20;
+    let details = ModuleDetailsBuilder::new()
+        .module_name("test")
+        .config(Some(config))
+        .transport(Arc::new(bus.clone()))
+        .build();
+    let _module = AlfredModule::new_with_details(details).await.expect("module should start on the memory bus");
+    bus.next_published().await.expect("bus is open");
+    for _ in 0..2 {
+        let (topic, heartbeat) = tokio::time::timeout(TIMEOUT, bus.next_published()).await
+            .expect("heartbeat should be published")
+            .expect("bus is open");
+        assert_eq!(topic, MODULE_HEARTBEAT_TOPIC);
+        assert_eq!(heartbeat.text, "test");
+    }
+}
+
+#[tokio::test]
+async fn modules_are_discovered_by_capability() {
+    let bus = MemoryBus::new();
+    for (module_name, capability) in [("whisper", "speech-to-text"), ("piper", "text-to-speech")] {
+        let details = ModuleDetailsBuilder::new()
+            .module_name(module_name)
+            .capabilities(BTreeMap::from([(capability.to_string(), "en".to_string())]))
+            .transport(Arc::new(bus.clone()))
+            .build();
+        let server = AlfredModule::new_with_details(details).await.expect("module should start on the memory bus");
+        tokio::spawn(async move { server.run().await });
+    }
+    let client = Connection::with_transport(&Config::default(), Arc::new(bus.clone())).await.expect("connection");
+    let modules = client.discover_modules(Duration::from_millis(100)).await.expect("discover");
+    let names: Vec<&str> = modules.iter().map(|module| module.name.as_str()).collect();
+    assert_eq!(names, ["piper", "whisper"]);
+    let modules = client.discover_modules_with_capability("speech-to-text", Duration::from_millis(100)).await.expect("discover");
+    assert_eq!(modules.len(), 1);
+    assert_eq!(modules[0].name, "whisper");
+}
+
+#[tokio::test]
+async fn lost_messages_are_dead_lettered() {
+    let bus = MemoryBus::new();
+    let mut server = module(&bus, "server").await;
+    server.on("fail", |_, _| async { Err(Error::ConversionError) }).await.expect("on");
+    server.on("echo", |_, message: Message| async move { Ok(Some(message)) }).await.expect("on");
+    server.listen("ignored").await.expect("listen");
+    tokio::spawn(async move { server.run().await });
+    bus.next_published().await.expect("bus is open");
+    let mut reasons = BTreeMap::new();
+    for topic in ["fail", "echo", "ignored"] {
+        // only received for the prefix of the subscriptions: dropped without dead letter
+        bus.inject(&format!("{topic}.response"), &text("unrelated"));
+        bus.inject(topic, &Message { text: topic.to_string(), ..Message::default() });
+        let (dead_letter_topic, dead_letter) = tokio::time::timeout(TIMEOUT, bus.next_published()).await
+            .expect("dead letter should be published")
+            .expect("bus is open");
+        assert_eq!(dead_letter_topic, DEAD_LETTER_TOPIC);
+        let dead_letter = DeadLetter::from_message(&dead_letter).expect("dead letter");
+        assert_eq!((dead_letter.topic.as_str(), dead_letter.module.as_str()), (topic, "server"));
+        reasons.insert(topic, dead_letter.reason);
+    }
+    assert!(reasons["fail"].starts_with("Handler failed"));
+    assert!(reasons["echo"].starts_with("Unable to reply"));
+    assert_eq!(reasons["ignored"], "No handler found");
+}
+
+#[tokio::test]
+async fn unmapped_topics_of_a_router_are_dead_lettered() {
+    let bus = MemoryBus::new();
+    let mut router = module(&bus, "routing").await;
+    router.on("chat", |_, _| async { Ok(None) }).await.expect("on");
+    router.dead_letter_prefixed();
+    tokio::spawn(async move { router.run_sequential().await });
+    bus.next_published().await.expect("bus is open");
+    bus.inject("chat", &text("routed"));
+    bus.inject("chat.unmapped", &text("lost"));
+    let (dead_letter_topic, dead_letter) = tokio::time::timeout(TIMEOUT, bus.next_published()).await
+        .expect("dead letter should be published")
+        .expect("bus is open");
+    assert_eq!(dead_letter_topic, DEAD_LETTER_TOPIC);
+    let dead_letter = DeadLetter::from_message(&dead_letter).expect("dead letter");
+    assert_eq!((dead_letter.topic.as_str(), dead_letter.module.as_str()), ("chat.unmapped", "routing"));
+    assert_eq!(dead_letter.message.text, "lost");
+    assert_eq!(dead_letter.reason, "No handler found");
+}
+
+#[tokio::test]
+async fn reliable_messages_are_acknowledged_and_deduplicated() {
+    let bus = MemoryBus::new();
+    let mut heater = module(&bus, "heater").await;
+    heater.listen("heater").await.expect("listen");
+    let receiving = tokio::spawn(async move {
+        let mut received = Vec::new();
+        for _ in 0..2 {
+            let (_, message) = heater.receive().await.expect("receive");
+            received.push(message.text);
+        }
+        received
+    });
+    let client = module(&bus, "client").await;
+    bus.next_published().await.expect("bus is open");
+    bus.next_published().await.expect("bus is open");
+    client.send_reliable("heater", &text("off")).await.expect("message should be acknowledged");
+    let (_, sent) = bus.next_published().await.expect("bus is open");
+    assert!(sent.params.contains_key(ACK_TOPIC_PARAM));
+    // a copy sent again, e.g. after a lost acknowledgement, is dropped
+    bus.inject("heater", &sent);
+    bus.inject("heater", &text("on"));
+    let received = tokio::time::timeout(TIMEOUT, receiving).await.expect("messages should be received").expect("task");
+    assert_eq!(received, ["off", "on"]);
+}
+
+#[tokio::test]
+async fn unacknowledged_messages_are_sent_again() {
+    let bus = MemoryBus::new();
+    let mut config = Config::default();
+    config.alfred.ack_timeout = 10;
+    config.alfred.ack_retries = 2;
+    let client = Connection::with_transport(&config, Arc::new(bus.clone())).await.expect("connection");
+    let result = client.send_reliable("nobody", &text("lost")).await;
+    assert!(matches!(result, Err(Error::AckTimeout(_))));
+    for _ in 0..3 {
+        let (topic, _) = bus.next_published().await.expect("bus is open");
+        assert_eq!(topic, "nobody");
+    }
+}
+
+#[tokio::test]
+async fn own_reliable_messages_are_not_acknowledged() {
+    let bus = MemoryBus::new();
+    let mut config = Config::default();
+    config.alfred.ack_timeout = 10;
+    config.alfred.ack_retries = 0;
+    let client = Connection::with_transport(&config, Arc::new(bus.clone())).await.expect("connection");
+    client.listen("loopback").await.expect("listen");
+    let result = client.send_reliable("loopback", &text("echo")).await;
+    assert!(matches!(result, Err(Error::AckTimeout(_))));
+}
+
+#[tokio::test]
+async fn observed_reliable_messages_are_not_acknowledged() {
+    let bus = MemoryBus::new();
+    let mut config = Config::default();
+    config.alfred.ack_timeout = 10;
+    config.alfred.ack_retries = 1;
+    let logs = Connection::with_transport(&config, Arc::new(bus.clone())).await.expect("connection");
+    logs.listen("").await.expect("listen");
+    let queue = Connection::with_transport(&config, Arc::new(bus.clone())).await.expect("connection");
+    queue.listen_passive("heater").await.expect("listen");
+    for observer in [logs, queue] {
+        tokio::spawn(async move { while observer.receive_all().await.is_ok() {} });
+    }
+    let client = Connection::with_transport(&config, Arc::new(bus.clone())).await.expect("connection");
+    let result = client.send_reliable("heater", &text("off")).await;
+    assert!(matches!(result, Err(Error::AckTimeout(_))));
+}
+
+#[tokio::test]
+async fn late_acknowledgements_are_dropped() {
+    let bus = MemoryBus::new();
+    for module_name in ["heater", "backup"] {
+        let mut heater = module(&bus, module_name).await;
+        heater.listen("heater").await.expect("listen");
+        tokio::spawn(async move { while heater.receive().await.is_ok() {} });
+    }
+    let client = Connection::with_transport(&Config::default(), Arc::new(bus.clone())).await.expect("connection");
+    client.listen("").await.expect("listen");
+    client.send_reliable("heater", &text("off")).await.expect("message should be acknowledged");
+    let mut acks = 0;
+    while acks < 2 {
+        let (topic, _) = tokio::time::timeout(TIMEOUT, bus.next_published()).await
+            .expect("both receivers should acknowledge")
+            .expect("bus is open");
+        if topic.starts_with(ACK_TOPIC_PREFIX) { acks += 1; }
+    }
+    // the second acknowledgement, received through the wildcard, does not reach the inbox
+    bus.inject("marker", &text("marker"));
+    loop {
+        let (topic, _) = tokio::time::timeout(TIMEOUT, client.receive_all()).await
+            .expect("marker should be received")
+            .expect("receive");
+        assert!(!topic.starts_with(ACK_TOPIC_PREFIX), "acknowledgement received on {topic}");
+        if topic == "marker" { break; }
+    }
+}
+
+#[tokio::test]
+async fn durable_subscriptions_are_announced() {
+    let bus = MemoryBus::new();
+    let mut sleeper = module(&bus, "sleeper").await;
+    bus.next_published().await.expect("bus is open");
+    sleeper.listen_durable("sensor.").await.expect("listen");
+    let (topic, message) = bus.next_published().await.expect("bus is open");
+    assert_eq!(topic, DURABLE_SUBSCRIBE_TOPIC);
+    let subscription = DurableSubscription::from_message(&message).expect("durable subscription");
+    assert_eq!(subscription, DurableSubscription { module: "sleeper".to_string(), topic: "sensor.".to_string(), max_size: None, max_age: None });
+    assert!(sleeper.info().topics.contains("sensor."));
+}
@@ -0,0 +1,229 @@
+use std::collections::{BTreeMap, LinkedList};
+use std::str::FromStr;
+use alfred_core::bytes::Bytes;
+use alfred_core::codec::CodecKind;
+use alfred_core::error::MessageCompressionError;
+use alfred_core::message::{Message, MessageType, CUSTOM_CODES};
+use proptest::prelude::*;
+
+fn message_type() -> impl Strategy<Value = MessageType> {
+    prop_oneof![
+        Just(MessageType::Unknown),
+        Just(MessageType::Text),
+        Just(MessageType::Audio),
+        Just(MessageType::Photo),
+        Just(MessageType::Video),
+        Just(MessageType::Document),
+        Just(MessageType::Location),
+        Just(MessageType::Command),
+        Just(MessageType::Event),
+        Just(MessageType::Json),
+        Just(MessageType::Error),
+        Just(MessageType::ModuleInfo),
+        (*CUSTOM_CODES.start()..=*CUSTOM_CODES.end()).prop_map(MessageType::Custom),
+        any::<String>().prop_map(MessageType::Named),
+    ]
+}
+
+prop_compose! {
+    fn message()(
+        message_type in message_type(),
+        params in prop::collection::btree_map(any::<String>(), any::<String>(), 0..32),
+        response_topics in prop::collection::vec(any::<String>(), 0..8),
+        sender in any::<String>(),
+        text in any::<String>(),
+        data in prop::collection::vec(any::<u8>(), 0..256),
+        id in any::<String>(),
+        correlation_id in any::<String>(),
+        timestamp in any::<u64>(),
+        hops in any::<u32>(),
+    ) -> Message {
+        Message {
+            message_type,
+            params,
+            response_topics: response_topics.into_iter().collect(),
+            sender,
+            text,
+            data: Bytes::from(data),
+            id,
+            correlation_id,
+            timestamp,
+            hops,
+        }
+    }
+}
+
+proptest! {
+    #[test]
+    fn custom_codes_never_corrupt_frames(code in any::<u8>(), text in any::<String>()) {
+        let message = Message { message_type: MessageType::Custom(code), text, ..Message::default() };
+        let decompressed = Message::decompress(&message.compress()).expect("valid frame");
+        prop_assert_eq!(&decompressed.text, &message.text);
+        match message.try_compress() {
+            Ok(frame) => prop_assert_eq!(Message::decompress(&frame).expect("valid frame"), message),
+            Err(e) => prop_assert!(matches!(e, MessageCompressionError::InvalidCustomCode(c) if c == code))
+        }
+    }
+
+    #[test]
+    fn decompress_never_panics(frame in prop::collection::vec(any::<u8>(), 0..512)) {
+        let _ = Message::decompress(&frame);
+    }
+
+    #[test]
+    fn decompress_never_panics_on_versioned_frames(frame in prop::collection::vec(any::<u8>(), 0..512)) {
+        let _ = Message::decompress(&[&[0x82][..], &frame].concat());
+    }
+
+    #[test]
+    fn decompress_never_panics_on_legacy_headers(
+        header in "[\\x00-\\x03\\u{FF}][\\x00-\\u{FF}]{2}",
+        body in prop::collection::vec(any::<u8>(), 0..512)
+    ) {
+        let _ = Message::decompress(&[header.as_bytes(), &body].concat());
+    }
+
+    #[test]
+    fn round_trip(message in message()) {
+        prop_assert_eq!(Message::decompress(&message.compress())?, message);
+    }
+
+    #[test]
+    fn truncated_frames_are_rejected(message in message(), cut in any::<prop::sample::Index>()) {
+        let compressed = message.compress();
+        let len = cut.index(compressed.len());
+        prop_assert!(Message::decompress(&compressed[..len]).is_err());
+    }
+
+    #[test]
+    fn corrupted_frames_never_panic(message in message(), position in any::<prop::sample::Index>(), byte in any::<u8>()) {
+        let mut compressed = message.compress();
+        let index = position.index(compressed.len());
+        compressed[index] = byte;
+        let _ = Message::decompress(&compressed);
+    }
+}
+
+#[test]
+fn more_than_255_params() {
+    let message = Message {
+        params: (0..300).map(|i| (format!("par{i}"), format!("val{i}"))).collect(),
+        ..Message::default()
+    };
+    assert_eq!(Message::decompress(&message.compress()).expect("valid v2 frame"), message);
+}
+
+#[test]
+fn bad_hops() {
+    let v2_frame = [0x82, 0x01, 0x00, 0x00, 0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0x7F];
+    assert!(matches!(Message::decompress(&v2_frame), Err(MessageCompressionError::BadCount(_))));
+}
+
+#[test]
+fn empty_frame() {
+    assert!(matches!(Message::decompress(&[]), Err(MessageCompressionError::EmptyFrame)));
+}
+
+#[test]
+fn unsupported_version() {
+    assert!(matches!(Message::decompress(&[0xBF, 0x01]), Err(MessageCompressionError::UnsupportedVersion(0x3F))));
+}
+
+#[test]
+fn bad_type_byte() {
+    assert!(matches!(Message::decompress("\u{100}\0\0\0\0".as_bytes()), Err(MessageCompressionError::MessageType(_))));
+    assert!(matches!(Message::decompress(&[0x82, 0xFE, 0x05, b'a']), Err(MessageCompressionError::TruncatedField(_))));
+}
+
+#[test]
+fn unknown_type_codes_are_custom() {
+    let v2_frame = [0x82, 0x42, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
+    let message = Message::decompress(&v2_frame).expect("valid v2 frame");
+    assert_eq!(message.message_type, MessageType::Custom(0x42));
+}
+
+#[test]
+fn bad_count() {
+    assert!(matches!(Message::decompress("\u{1}\u{100}\0".as_bytes()), Err(MessageCompressionError::BadCount(_))));
+    let overflowing_count = [&[0x82, 0x01][..], &[0xFF; 10], &[0x01]].concat();
+    assert!(matches!(Message::decompress(&overflowing_count), Err(MessageCompressionError::BadCount(_))));
+}
+
+#[test]
+fn truncated_params() {
+    let v1_frame = "\u{1}\u{3}\u{0}par1\0val1\0";
+    assert!(matches!(
+        Message::decompress(v1_frame.as_bytes()),
+        Err(MessageCompressionError::TruncatedParams { expected: 3, found: 1 })
+    ));
+    let v2_frame = [&[0x82, 0x01, 0x00, 0x00, 0x00, 0x00, 0x02, 0x04][..], b"par1", &[0x04], b"val1"].concat();
+    assert!(matches!(
+        Message::decompress(&v2_frame),
+        Err(MessageCompressionError::TruncatedParams { expected: 2, found: 1 })
+    ));
+}
+
+#[test]
+fn truncated_field() {
+    let v2_frame = [&[0x82, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x10][..], b"0123"].concat();
+    assert!(matches!(Message::decompress(&v2_frame), Err(MessageCompressionError::TruncatedField(_))));
+}
+
+#[test]
+fn invalid_utf8() {
+    let v2_frame = [0x82, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0xC3, 0x28];
+    assert!(matches!(Message::decompress(&v2_frame), Err(MessageCompressionError::InvalidUtf8(_))));
+}
+
+#[test]
+fn legacy_message_without_response_topics() {
+    let v1_frame = "\u{1}\u{1}\u{0}par1\0val1\0\u{0}0123\0text";
+    let message = Message::decompress(v1_frame.as_bytes()).expect("valid v1 frame");
+    assert_eq!(message, Message {
+        message_type: MessageType::Text,
+        params: BTreeMap::from([(String::from("par1"), String::from("val1"))]),
+        response_topics: LinkedList::new(),
+        sender: String::from("0123"),
+        text: String::from("text"),
+        ..Message::default()
+    });
+}
+
+#[test]
+fn legacy_text_keeps_its_nul_characters() {
+    let v1_frame = "\u{1}\u{0}\u{0}\u{0}0123\0multi\0line";
+    let message = Message::decompress(v1_frame.as_bytes()).expect("valid v1 frame");
+    assert_eq!(message.text, "multi\0line");
+    assert!(message.data.is_empty());
+}
+
+#[test]
+fn legacy_params_are_kept() {
+    for size in ["3", "abc"] {
+        let v1_frame = format!("\u{1}\u{1}\u{0}alfred.data\0{size}\0\00123\0text\0abc");
+        let message = Message::decompress(v1_frame.as_bytes()).expect("valid v1 frame");
+        assert_eq!(message.text, "text\0abc");
+        assert!(message.data.is_empty());
+        assert_eq!(message.params.get("alfred.data").map(String::as_str), Some(size));
+    }
+}
+
+#[test]
+fn reserved_custom_codes_are_rejected() {
+    assert!(matches!(MessageType::custom(0x80), Ok(MessageType::Custom(0x80))));
+    for code in [0x01, 0x42, 0xFE, 0xFF] {
+        assert!(matches!(MessageType::custom(code), Err(MessageCompressionError::InvalidCustomCode(c)) if c == code));
+    }
+    for code in [0x01, 0xFE, 0xFF] {
+        let message = Message { message_type: MessageType::Custom(code), ..Message::default() };
+        assert!(matches!(message.try_compress(), Err(MessageCompressionError::InvalidCustomCode(_))));
+        assert_eq!(Message::decompress(&message.compress()).expect("valid frame").message_type, MessageType::Unknown);
+        for kind in [CodecKind::Native, CodecKind::Json, CodecKind::MessagePack] {
+            if let Ok(codec) = kind.codec() {
+                assert!(matches!(codec.encode(&message), Err(MessageCompressionError::InvalidCustomCode(c)) if c == code));
+            }
+        }
+        assert!(MessageType::from_str(&format!("Custom({code})")).is_err());
+    }
+    assert_eq!(MessageType::from_str("Custom(128)"), Ok(MessageType::Custom(0x80)));
+}
@@ -0,0 +1,156 @@
+use std::sync::Arc;
+use std::time::Duration;
+use alfred_core::config::Config;
+use alfred_core::connection::{Connection, ACK_TOPIC_PREFIX};
+use alfred_core::durable::DurableSubscription;
+use alfred_core::memory::MemoryBus;
+use alfred_core::message::Message;
+use alfred_core::queue::{set_online, Queues};
+use alfred_core::tokio::sync::Mutex;
+use common::module;
+
+mod common;
+
+/// Replays are retried with a backoff
+const REPLAY_TIMEOUT: Duration = Duration::from_secs(5);
+
+fn queue_dir() -> std::path::PathBuf {
+    std::env::temp_dir().join(format!("alfred-queue-{}", Message::new_id()))
+}
+
+fn subscription(module: &str, topic: &str, max_size: Option<usize>, max_age: Option<u64>) -> DurableSubscription {
+    DurableSubscription { module: module.to_string(), topic: topic.to_string(), max_size, max_age }
+}
+
+fn stamped(text: &str) -> Message {
+    let mut message = common::text(text);
+    message.stamp();
+    message
+}
+
+fn texts(stored: &[(String, Message)]) -> Vec<&str> {
+    stored.iter().map(|(_, message)| message.text.as_str()).collect()
+}
+
+#[test]
+fn messages_are_stored_for_the_offline_modules() {
+    let dir = queue_dir();
+    let mut queues = Queues::load(dir.clone());
+    queues.subscribe(subscription("heater", "heater.", None, None));
+    queues.subscribe(subscription("sensor", "sensor.", None, None));
+    queues.set_online("heater", false);
+    queues.store("heater.power", &stamped("off"));
+    queues.store("sensor.temperature", &stamped("20"));
+    queues.store("lights.power", &stamped("on"));
+    // the age limit does not depend on the timestamp given by the sender
+    queues.store("heater.power", &Message { text: "on".to_string(), ..Message::default() });
+    assert_eq!(texts(&queues.stored("heater")), ["off", "on"]);
+    assert!(queues.stored("sensor").is_empty());
+
+    let loaded = Queues::load(dir.clone());
+    assert_eq!(loaded.modules(), ["heater", "sensor"]);
+    assert_eq!(loaded.topics(), ["heater.".to_string(), "sensor.".to_string()].into());
+    let stored = loaded.stored("heater");
+    assert_eq!(texts(&stored), ["off", "on"]);
+    assert_eq!(stored[0].0, "heater.power");
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+#[test]
+fn queues_are_trimmed() {
+    let dir = queue_dir();
+    let mut queues = Queues::load(dir.clone());
+    queues.subscribe(subscription("heater", "heater.", Some(2), None));
+    queues.subscribe(subscription("sensor", "sensor.", None, Some(50)));
+    queues.set_online("heater", false);
+    queues.set_online("sensor", false);
+    for power in ["1", "2", "3"] {
+        queues.store("heater.power", &stamped(power));
+    }
+    assert_eq!(texts(&queues.stored("heater")), ["2", "3"]);
+
+    queues.store("sensor.temperature", &Message { text: "20".to_string(), timestamp: 1, ..Message::default() });
+    std::thread::sleep(Duration::from_millis(100));
+    queues.store("sensor.temperature", &stamped("21"));
+    assert_eq!(texts(&queues.stored("sensor")), ["21"]);
+    assert_eq!(texts(&Queues::load(dir.clone()).stored("heater")), ["2", "3"]);
+    assert_eq!(texts(&Queues::load(dir.clone()).stored("sensor")), ["21"]);
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+#[tokio::test]
+async fn stored_messages_are_replayed_once_the_module_listens() {
+    let bus = MemoryBus::new();
+    let dir = queue_dir();
+    let mut queues = Queues::load(dir.clone());
+    queues.subscribe(subscription("heater", "heater.", None, None));
+    queues.set_online("heater", false);
+    queues.store("heater.power", &stamped("off"));
+    queues.store("heater.power", &stamped("on"));
+    let queues = Arc::new(Mutex::new(queues));
+    let mut config = Config::default();
+    config.alfred.ack_timeout = 10;
+    config.alfred.ack_retries = 0;
+    let connection = Connection::with_transport(&config, Arc::new(bus.clone())).await.expect("connection");
+    set_online(&connection, &queues, "heater", true).await;
+
+    // announced online before it subscribes again: the first attempts are not acknowledged
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let mut heater = module(&bus, "heater").await;
+    heater.listen("heater.").await.expect("listen");
+    let mut received = Vec::new();
+    for _ in 0..2 {
+        let (topic, message) = tokio::time::timeout(REPLAY_TIMEOUT, heater.receive()).await
+            .expect("stored messages should be replayed")
+            .expect("receive");
+        assert_eq!(topic, "heater.power");
+        received.push(message.text);
+    }
+    assert_eq!(received, ["off", "on"]);
+
+    tokio::time::timeout(REPLAY_TIMEOUT, async {
+        while !queues.lock().await.stored("heater").is_empty() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }).await.expect("replayed messages should be dropped");
+    assert!(Queues::load(dir.clone()).stored("heater").is_empty());
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+#[tokio::test]
+async fn replays_are_only_received_by_their_module() {
+    let bus = MemoryBus::new();
+    let dir = queue_dir();
+    let mut queues = Queues::load(dir.clone());
+    queues.subscribe(subscription("heater", "heater.", None, None));
+    queues.set_online("heater", false);
+    queues.store("heater.power", &stamped("off"));
+    let queues = Arc::new(Mutex::new(queues));
+    let mut thermostat = module(&bus, "thermostat").await;
+    thermostat.listen("heater.").await.expect("listen");
+    let (sender, mut thermostat_received) = tokio::sync::mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        while let Ok((topic, _)) = thermostat.receive().await {
+            let _ = sender.send(topic);
+        }
+    });
+    let connection = Connection::with_transport(&Config::default(), Arc::new(bus.clone())).await.expect("connection");
+    set_online(&connection, &queues, "heater", true).await;
+
+    // the heater is not connected yet: nobody else acknowledges the replays
+    let watching = tokio::time::timeout(Duration::from_millis(300), async {
+        loop {
+            let (topic, _) = bus.next_published().await.expect("bus is open");
+            assert!(!topic.starts_with(ACK_TOPIC_PREFIX), "replay acknowledged on {topic}");
+        }
+    });
+    assert!(watching.await.is_err());
+    assert_eq!(texts(&queues.lock().await.stored("heater")), ["off"]);
+    let heater = module(&bus, "heater").await;
+    let (topic, message) = tokio::time::timeout(REPLAY_TIMEOUT, heater.receive()).await
+        .expect("stored messages should be replayed")
+        .expect("receive");
+    assert_eq!((topic.as_str(), message.text.as_str()), ("heater.power", "off"));
+    assert!(thermostat_received.try_recv().is_err(), "replay received by another module");
+    let _ = std::fs::remove_dir_all(dir);
+}
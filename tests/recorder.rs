@@ -0,0 +1,112 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use bytes::Bytes;
+use alfred_core::codec;
+use alfred_core::connection::{ACK_TOPIC_PARAM, PROBE_TOPIC_PREFIX};
+use alfred_core::message::Message;
+use alfred_core::recorder::{self, Record};
+use alfred_core::transport::{Transport, ZmqTransport};
+use common::{broker, TIMEOUT};
+
+mod common;
+
+fn recording_path() -> PathBuf {
+    std::env::temp_dir().join(format!("alfred-recording-{}", Message::new_id()))
+}
+
+fn frames(topic: &str, message: &Message) -> Vec<Bytes> {
+    vec![Bytes::from(topic.to_string()), Bytes::from(message.try_compress().expect("message should be encoded"))]
+}
+
+/// Records which could be read so far, the recording being written by another task.
+fn records(path: &Path) -> Vec<Record> {
+    let Ok(mut reader) = File::open(path).map(BufReader::new) else { return Vec::new() };
+    let mut header = [0; 10];
+    if std::io::Read::read_exact(&mut reader, &mut header).is_err() { return Vec::new(); }
+    std::iter::from_fn(|| Record::read(&mut reader).ok().flatten()).collect()
+}
+
+#[tokio::test]
+async fn raw_frames_are_recorded() {
+    let config = broker().await;
+    let transport = ZmqTransport::new(&config);
+    let path = recording_path();
+    let (mut subscriber, _) = transport.connect_raw().await.expect("raw connection");
+    let mut writer = BufWriter::new(File::create(&path).expect("recording file"));
+    let recording = tokio::spawn(async move { recorder::record(subscriber.as_mut(), &mut writer).await });
+    let (_, mut publisher) = transport.connect_raw().await.expect("raw connection");
+    tokio::time::timeout(TIMEOUT, async {
+        while records(&path).is_empty() {
+            publisher.send(frames("sync", &Message::default())).await.expect("send");
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    }).await.expect("recorder should connect");
+
+    let mut reliable = Message { text: "off".to_string(), ..Message::default() };
+    reliable.stamp();
+    reliable.params.insert(ACK_TOPIC_PARAM.to_string(), "alfred.ack.test".to_string());
+    let malformed = vec![Bytes::from_static(b"heater"), Bytes::from_static(&[0xFF, 0x00, 0x42])];
+    let messages = [frames("heater", &reliable), frames("heater", &reliable), malformed];
+    for message in &messages {
+        publisher.send(message.clone()).await.expect("send");
+    }
+    let recorded = tokio::time::timeout(TIMEOUT, async {
+        loop {
+            let recorded: Vec<_> = records(&path).into_iter().map(|record| record.frames).filter(|frames| frames[0] != "sync").collect();
+            if recorded.len() >= messages.len() { return recorded; }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }).await.expect("messages should be recorded");
+    recording.abort();
+    // copies, params and malformed messages are recorded as published
+    assert_eq!(recorded, messages);
+    let decoded = codec::decode(&recorded[0][1]).expect("message");
+    assert_eq!(decoded.params.get(ACK_TOPIC_PARAM).map(String::as_str), Some("alfred.ack.test"));
+    let _ = std::fs::remove_file(path);
+}
+
+#[tokio::test]
+async fn recordings_are_replayed_as_recorded() {
+    let config = broker().await;
+    let transport = ZmqTransport::new(&config);
+    let (mut subscriber, mut publisher) = transport.connect_raw().await.expect("raw connection");
+    subscriber.listen("").await.expect("listen");
+
+    let path = recording_path();
+    let malformed = vec![Bytes::from_static(b"heater"), Bytes::from_static(&[0xFF])];
+    let recorded = [
+        Record { timestamp: 1000, frames: frames("heater", &Message { text: "off".to_string(), ..Message::default() }) },
+        Record { timestamp: 1100, frames: frames("lights", &Message { text: "on".to_string(), ..Message::default() }) },
+        Record { timestamp: 1200, frames: malformed.clone() }
+    ];
+    {
+        let mut writer = BufWriter::new(File::create(&path).expect("recording file"));
+        recorder::write_header(&mut writer).expect("header");
+        for record in &recorded {
+            record.write(&mut writer).expect("record");
+        }
+    }
+    let started = Instant::now();
+    let mut reader = BufReader::new(File::open(&path).expect("recording file"));
+    let count = recorder::replay(subscriber.as_mut(), publisher.as_mut(), &mut reader, 2.0, &["heater".to_string()], TIMEOUT).await.expect("replay");
+    assert_eq!(count, 2);
+    // 200 ms between the two heater messages, twice as fast
+    assert!(started.elapsed() >= Duration::from_millis(100));
+    // the probes of the handshake are received too, through the wildcard
+    let received = tokio::time::timeout(TIMEOUT, async {
+        let mut received = Vec::new();
+        while received.len() < 2 {
+            let frames = subscriber.receive().await.expect("receive");
+            if !frames[0].starts_with(PROBE_TOPIC_PREFIX.as_bytes()) { received.push(frames); }
+        }
+        received
+    }).await.expect("messages should be replayed");
+    assert_eq!(received, [recorded[0].frames.clone(), malformed]);
+
+    let mut not_a_recording = BufReader::new(File::open(&path).expect("recording file"));
+    std::io::Read::read_exact(&mut not_a_recording, &mut [0; 3]).expect("read");
+    assert!(recorder::replay(subscriber.as_mut(), publisher.as_mut(), &mut not_a_recording, 0.0, &[], TIMEOUT).await.is_err());
+    let _ = std::fs::remove_file(path);
+}
@@ -0,0 +1,93 @@
+use alfred_core::message::{Message, MessageType};
+use alfred_core::module_info::ModuleInfo;
+use alfred_core::registry::{Directory, LAST_SEEN_PARAM};
+
+fn info(name: &str, version: &str, capability: &str) -> Message {
+    let info = ModuleInfo {
+        name: name.to_string(),
+        version: version.to_string(),
+        topics: [format!("{name}.request")].into(),
+        capabilities: [(capability.to_string(), String::new())].into(),
+        ..ModuleInfo::default()
+    };
+    Message { timestamp: 1000, ..info.to_message() }
+}
+
+fn heartbeat(name: &str, version: &str, timestamp: u64) -> Message {
+    Message {
+        text: name.to_string(),
+        timestamp,
+        params: [("version".to_string(), version.to_string())].into(),
+        ..Message::default()
+    }
+}
+
+#[test]
+fn modules_are_listed_by_capability() {
+    let mut directory = Directory::default();
+    directory.update_info(&info("whisper", "1.0.0", "speech-to-text"));
+    directory.update_info(&info("piper", "2.0.0", "text-to-speech"));
+    directory.update_info(&Message::default());
+    assert_eq!(directory.list(None), [("piper".to_string(), "2.0.0".to_string()), ("whisper".to_string(), "1.0.0".to_string())].into());
+    assert_eq!(directory.list(Some("speech-to-text")), [("whisper".to_string(), "1.0.0".to_string())].into());
+    assert!(directory.list(Some("translation")).is_empty());
+}
+
+#[test]
+fn lookups_return_the_module_info() {
+    let mut directory = Directory::default();
+    directory.update_info(&info("whisper", "1.0.0", "speech-to-text"));
+    let reply = directory.lookup("whisper");
+    assert_eq!(reply.params.get(LAST_SEEN_PARAM), Some(&"1000".to_string()));
+    let found = ModuleInfo::from_message(&reply).expect("module info");
+    assert_eq!(found.version, "1.0.0");
+    assert!(found.has_capability("speech-to-text"));
+    assert!(found.topics.contains("whisper.request"));
+
+    let unknown = directory.lookup("piper");
+    assert_eq!(unknown.message_type, MessageType::Error);
+    assert_eq!(unknown.text, "Unknown module piper");
+}
+
+#[test]
+fn heartbeats_are_merged_with_the_module_info() {
+    let mut directory = Directory::default();
+    directory.update_heartbeat(&heartbeat("piper", "2.0.0", 500));
+    let found = ModuleInfo::from_message(&directory.lookup("piper")).expect("module info");
+    assert_eq!(found.version, "2.0.0");
+    assert!(found.capabilities.is_empty());
+
+    directory.update_info(&info("whisper", "1.0.0", "speech-to-text"));
+    directory.update_heartbeat(&heartbeat("whisper", "1.1.0", 2000));
+    directory.update_heartbeat(&heartbeat("", "3.0.0", 2000));
+    let reply = directory.lookup("whisper");
+    assert_eq!(reply.params.get(LAST_SEEN_PARAM), Some(&"2000".to_string()));
+    let found = ModuleInfo::from_message(&reply).expect("module info");
+    assert_eq!(found.version, "1.1.0");
+    assert!(found.has_capability("speech-to-text"));
+    assert_eq!(directory.list(None).len(), 2);
+}
+
+#[test]
+fn snapshots_are_loaded_back() {
+    let dir = std::env::temp_dir().join(format!("alfred-registry-{}", Message::new_id()));
+    let path = dir.join("registry.toml");
+    assert!(Directory::load(&path).list(None).is_empty());
+
+    let mut directory = Directory::default();
+    directory.update_info(&info("whisper", "1.0.0", "speech-to-text"));
+    directory.update_heartbeat(&heartbeat("piper", "2.0.0", 500));
+    assert!(directory.is_changed());
+    directory.save(&path).expect("snapshot should be saved");
+    assert!(!directory.is_changed());
+
+    let loaded = Directory::load(&path);
+    assert!(!loaded.is_changed());
+    assert_eq!(loaded.list(None), directory.list(None));
+    assert_eq!(loaded.lookup("whisper").params, directory.lookup("whisper").params);
+    assert_eq!(loaded.lookup("piper").params, directory.lookup("piper").params);
+
+    std::fs::write(&path, "not a snapshot").expect("snapshot should be overwritten");
+    assert!(Directory::load(&path).list(None).is_empty());
+    let _ = std::fs::remove_dir_all(dir);
+}